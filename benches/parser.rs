@@ -0,0 +1,85 @@
+//! Benchmarks of the parsing hot path, run with `cargo bench`.
+
+use collect_unmarked_tests::{DEFAULT_EXCLUDE_MARKERS, find_python_test_functions};
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::hint::black_box;
+
+/// A test module mixing the constructs found in real test suites: module and class
+/// `pytestmark`, stacked and multi-line decorators, fixtures, test classes, helpers and
+/// docstrings.
+fn corpus(modules: usize) -> String {
+    let mut content = String::new();
+    for module in 0..modules {
+        write!(
+            content,
+            r#"
+import pytest
+
+pytestmark = [pytest.mark.integration]
+
+
+@pytest.fixture
+def client_{module}():
+    """Client talking to a test server."""
+    return object()
+
+
+@pytest.mark.unit
+@pytest.mark.parametrize(
+    "value, expected",
+    [
+        (1, 2),
+        pytest.param(2, 3, marks=pytest.mark.slow),
+    ],
+)
+def test_increment_{module}(value, expected):
+    assert value + 1 == expected
+
+
+def test_unmarked_{module}(client_{module}):
+    # def test_in_comment(): ...
+    text = """
+    def test_in_string():
+        pass
+    """
+    assert text
+
+
+class TestApi{module}:
+    pytestmark = pytest.mark.component
+
+    @pytest.mark.skip(reason="flaky")
+    def test_skipped(self):
+        pass
+
+    async def test_async(self):
+        pass
+
+    def helper(self):
+        return 1
+"#
+        )
+        .unwrap();
+    }
+    content
+}
+
+fn bench_find_python_test_functions(c: &mut Criterion) {
+    let content = corpus(50);
+    let exclude_markers: HashSet<String> = DEFAULT_EXCLUDE_MARKERS
+        .iter()
+        .map(|marker| marker.to_string())
+        .collect();
+
+    let mut group = c.benchmark_group("find_python_test_functions");
+    group.throughput(Throughput::Bytes(content.len() as u64));
+    group.bench_function("regex", |b| {
+        b.iter(|| find_python_test_functions(black_box(&content), &exclude_markers))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_find_python_test_functions);
+criterion_main!(benches);
@@ -0,0 +1,107 @@
+//! Reading test sources from `.tar`, `.tar.gz` and `.zip` archives, e.g. sdists and wheels.
+
+use flate2::read::GzDecoder;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Name suffixes of the archives that can be scanned, by format.
+const TAR_GZ_SUFFIXES: &[&str] = &[".tar.gz", ".tgz"];
+const TAR_SUFFIXES: &[&str] = &[".tar"];
+const ZIP_SUFFIXES: &[&str] = &[".zip", ".whl"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl Format {
+    fn of(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        let has_suffix = |suffixes: &[&str]| suffixes.iter().any(|suffix| name.ends_with(suffix));
+        if has_suffix(TAR_GZ_SUFFIXES) {
+            Some(Format::TarGz)
+        } else if has_suffix(TAR_SUFFIXES) {
+            Some(Format::Tar)
+        } else if has_suffix(ZIP_SUFFIXES) {
+            Some(Format::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// A file within an archive.
+#[derive(Debug)]
+pub(crate) struct Member {
+    /// Path of the member within the archive
+    pub path: PathBuf,
+    /// Contents of the member, if it could be read as UTF-8
+    pub content: io::Result<String>,
+}
+
+/// Whether `path` names an archive that can be scanned, judging by its file name.
+pub fn is_archive(path: &Path) -> bool {
+    Format::of(path).is_some()
+}
+
+/// The path findings in `member` of `archive` are reported under, e.g.
+/// `dist/pkg.tar.gz!pkg/tests/test_x.py`.
+pub(crate) fn member_path(archive: &Path, member: &Path) -> PathBuf {
+    PathBuf::from(format!("{}!{}", archive.display(), member.display()))
+}
+
+/// Read the regular files in `archive` whose path passes `select`, in archive order.
+///
+/// Fails if `archive` can't be opened or isn't a valid archive; members that can't be read
+/// are returned with their error.
+pub(crate) fn read_members(
+    archive: &Path,
+    select: impl Fn(&Path) -> bool,
+) -> io::Result<Vec<Member>> {
+    let unsupported = || io::Error::new(io::ErrorKind::InvalidInput, "not a supported archive");
+    let file = File::open(archive)?;
+    match Format::of(archive).ok_or_else(unsupported)? {
+        Format::Tar => read_tar_members(file, select),
+        Format::TarGz => read_tar_members(GzDecoder::new(file), select),
+        Format::Zip => read_zip_members(file, select),
+    }
+}
+
+fn read_tar_members(reader: impl Read, select: impl Fn(&Path) -> bool) -> io::Result<Vec<Member>> {
+    let mut members = Vec::new();
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path()?.into_owned();
+        if select(&path) {
+            let mut content = String::new();
+            let content = entry.read_to_string(&mut content).map(|_| content);
+            members.push(Member { path, content });
+        }
+    }
+    Ok(members)
+}
+
+fn read_zip_members(file: File, select: impl Fn(&Path) -> bool) -> io::Result<Vec<Member>> {
+    let mut members = Vec::new();
+    let mut archive = zip::ZipArchive::new(file)?;
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        // Skip directories and names escaping the archive, like `../x.py`
+        let Some(path) = entry.enclosed_name().filter(|_| entry.is_file()) else {
+            continue;
+        };
+        if select(&path) {
+            let mut content = String::new();
+            let content = entry.read_to_string(&mut content).map(|_| content);
+            members.push(Member { path, content });
+        }
+    }
+    Ok(members)
+}
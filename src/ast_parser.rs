@@ -0,0 +1,606 @@
+//! Test discovery backed by a real Python syntax tree.
+//!
+//! Produces the same results as the regex scanner in `parser.rs`, but is not fooled by
+//! `def test_...` inside strings or comments, and understands multi-line signatures and
+//! arbitrarily formatted decorator expressions.
+
+use rustpython_parser::ast::{self, Ranged, Stmt};
+use rustpython_parser::{Parse, ParseError};
+use std::collections::{HashMap, HashSet};
+
+use crate::parser::{
+    BODY_SKIP_STATEMENTS, aliased_marker, extract_markers, extract_pytestmark_value_markers,
+    is_fixture_decorator, is_ignored_decorator, is_non_instance_method_decorator,
+    is_suppression_comment, is_test_class, mark_alias_target, mask_strings_and_comments,
+    parametrize_case_markers, parametrize_cases,
+};
+use crate::{MarkerSources, ParseOptions, TestFunction};
+
+/// Find test functions in `content` that carry none of `exclude_markers`.
+pub fn find_python_test_functions(
+    content: &str,
+    exclude_markers: &HashSet<String>,
+    options: &ParseOptions,
+) -> Result<Vec<TestFunction>, ParseError> {
+    Ok(collect_python_tests(content, options)?
+        .into_iter()
+        .filter(|test| !test.has_any_marker(exclude_markers))
+        .collect())
+}
+
+/// Find all test functions in `content`, together with the markers applied to each.
+pub fn collect_python_tests(
+    content: &str,
+    options: &ParseOptions,
+) -> Result<Vec<TestFunction>, ParseError> {
+    let suite = ast::Suite::parse(content, "<test file>")?;
+
+    let mut visitor = Visitor {
+        content,
+        lines: content.lines().collect(),
+        line_starts: std::iter::once(0)
+            .chain(content.match_indices('\n').map(|(offset, _)| offset + 1))
+            .collect(),
+        options,
+        class_path: Vec::new(),
+        class_markers: HashMap::new(),
+        mark_aliases: Vec::new(),
+        test_functions: Vec::new(),
+    };
+    visitor.mark_aliases = visitor.module_assignments(&suite);
+    let module_markers = MarkerSources {
+        module: visitor.pytestmark_markers(&suite).into_iter().collect(),
+        ..MarkerSources::default()
+    };
+    visitor.visit_body(&suite, &module_markers);
+
+    Ok(visitor.test_functions)
+}
+
+struct Visitor<'a> {
+    content: &'a str,
+    lines: Vec<&'a str>,
+    /// Byte offset at which each line of `content` starts
+    line_starts: Vec<usize>,
+    options: &'a ParseOptions,
+    /// Names of the test classes enclosing the visited body, outermost first
+    class_path: Vec<String>,
+    /// Markers applied by each class seen so far, including those it inherits, by name
+    class_markers: HashMap<String, HashSet<String>>,
+    /// Module-level assignments in source order: where they start, the assigned name and
+    /// the marker it applies if the value is a mark, e.g. for `custom = pytest.mark.unit`
+    mark_aliases: Vec<(usize, String, Option<String>)>,
+    test_functions: Vec<TestFunction>,
+}
+
+impl Visitor<'_> {
+    /// Visit statements of a module or class body; `inherited` holds the markers applied
+    /// by all enclosing scopes, before aliases are applied.
+    fn visit_body(&mut self, body: &[Stmt], inherited: &MarkerSources) {
+        for stmt in body {
+            match stmt {
+                Stmt::FunctionDef(ast::StmtFunctionDef {
+                    name,
+                    body,
+                    decorator_list,
+                    ..
+                })
+                | Stmt::AsyncFunctionDef(ast::StmtAsyncFunctionDef {
+                    name,
+                    body,
+                    decorator_list,
+                    ..
+                }) if self.is_test_name(name.as_str())
+                    && self.is_collected(decorator_list)
+                    && !self.is_suppressed(stmt, decorator_list) =>
+                {
+                    let mut function_markers = self.decorator_markers(decorator_list);
+                    if self.options.detect_body_skips
+                        && self.starts_with_skip(body)
+                        && !self.options.ignored_markers.contains("skip")
+                    {
+                        function_markers.insert("skip".to_string());
+                    }
+                    let marker_sources = MarkerSources {
+                        module: self
+                            .options
+                            .resolve_markers(inherited.module.iter().cloned()),
+                        class: self
+                            .options
+                            .resolve_markers(inherited.class.iter().cloned()),
+                        function: self.options.resolve_markers(function_markers),
+                    };
+
+                    self.test_functions.push(TestFunction::new(
+                        name.to_string(),
+                        self.class_path.clone(),
+                        self.line_of(stmt),
+                        marker_sources,
+                        self.parametrize_cases(decorator_list),
+                    ));
+                }
+                Stmt::ClassDef(class) => {
+                    // Like pytest, classes inherit the markers of their base classes; only
+                    // bases defined earlier in the same file can be followed
+                    let mut class_markers = self.decorator_markers(&class.decorator_list);
+                    class_markers.extend(self.pytestmark_markers(&class.body));
+                    for base in &class.bases {
+                        if let ast::Expr::Name(base) = base
+                            && let Some(markers) = self.class_markers.get(base.id.as_str())
+                        {
+                            class_markers.extend(markers.iter().cloned());
+                        }
+                    }
+                    self.class_markers
+                        .insert(class.name.to_string(), class_markers.clone());
+
+                    // Only the markers of test classes apply to the tests within them
+                    let bases: Vec<&str> =
+                        class.bases.iter().map(|base| self.source(base)).collect();
+                    let mut markers = inherited.clone();
+                    if is_test_class(class.name.as_str(), &bases, self.options) {
+                        markers.class.extend(class_markers);
+                    }
+                    self.class_path.push(class.name.to_string());
+                    self.visit_body(&class.body, &markers);
+                    self.class_path.pop();
+                }
+                // Functions defined conditionally at module or class level are still collected
+                Stmt::If(ast::StmtIf { body, orelse, .. })
+                | Stmt::For(ast::StmtFor { body, orelse, .. })
+                | Stmt::While(ast::StmtWhile { body, orelse, .. }) => {
+                    self.visit_body(body, inherited);
+                    self.visit_body(orelse, inherited);
+                }
+                Stmt::With(ast::StmtWith { body, .. }) => self.visit_body(body, inherited),
+                Stmt::Try(ast::StmtTry {
+                    body,
+                    handlers,
+                    orelse,
+                    finalbody,
+                    ..
+                }) => {
+                    self.visit_body(body, inherited);
+                    for handler in handlers {
+                        let ast::ExceptHandler::ExceptHandler(handler) = handler;
+                        self.visit_body(&handler.body, inherited);
+                    }
+                    self.visit_body(orelse, inherited);
+                    self.visit_body(finalbody, inherited);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn is_test_name(&self, name: &str) -> bool {
+        self.options.test_prefixes.iter().any(|prefix| {
+            name.strip_prefix(prefix.as_str())
+                .is_some_and(|rest| !rest.is_empty())
+        })
+    }
+
+    /// Whether a function with a test name and the given decorators is collected
+    fn is_collected(&self, decorators: &[ast::Expr]) -> bool {
+        let decorators: Vec<String> = decorators
+            .iter()
+            .map(|decorator| format!("@{}", self.source(decorator)))
+            .collect();
+
+        // Fixtures are never collected as tests, whatever their name
+        let is_fixture = decorators.iter().any(|line| is_fixture_decorator(line));
+        let is_skipped_method = self.options.skip_non_instance_methods
+            && !self.class_path.is_empty()
+            && decorators
+                .iter()
+                .any(|line| is_non_instance_method_decorator(line));
+
+        !is_fixture && !is_skipped_method
+    }
+
+    /// Whether a suppression comment precedes the definition `stmt`, either among its
+    /// decorators or in the comments directly above them.
+    fn is_suppressed(&self, stmt: &Stmt, decorators: &[ast::Expr]) -> bool {
+        let def_line = self.line_of(stmt);
+        let first_line = decorators
+            .iter()
+            .map(|decorator| self.line_of(decorator))
+            .min()
+            .unwrap_or(def_line);
+        let lines = &self.lines[..def_line - 1];
+
+        let mut start = first_line - 1;
+        while start > 0 {
+            let line = lines[start - 1].trim();
+            if !line.is_empty() && !line.starts_with('#') {
+                break;
+            }
+            start -= 1;
+        }
+
+        lines[start..]
+            .iter()
+            .any(|line| is_suppression_comment(line.trim()))
+    }
+
+    fn decorator_markers(&self, decorators: &[ast::Expr]) -> HashSet<String> {
+        let mark_aliases = match decorators.first() {
+            Some(decorator) => self.mark_aliases_at(usize::from(decorator.start())),
+            None => HashMap::new(),
+        };
+        decorators
+            .iter()
+            .map(|decorator| format!("@{}", self.source(decorator)))
+            .filter(|decorator| !is_ignored_decorator(decorator, self.options))
+            .flat_map(|decorator| {
+                // A decorator expression applies at most one marker
+                let mut markers: HashSet<String> = match aliased_marker(&decorator, &mark_aliases) {
+                    Some(marker) => HashSet::from([marker.clone()]),
+                    None => extract_markers(&decorator, self.options.strict_marks)
+                        .into_iter()
+                        .take(1)
+                        .collect(),
+                };
+                if self.options.param_marks {
+                    markers.extend(parametrize_case_markers(&mask_strings_and_comments(
+                        &decorator,
+                    )));
+                }
+                markers
+            })
+            .filter(|marker| !self.options.ignored_markers.contains(marker))
+            .collect()
+    }
+
+    /// Names assigned at module level, in source order, with the marker each applies if
+    /// the assigned value is a mark.
+    fn module_assignments(&self, suite: &[Stmt]) -> Vec<(usize, String, Option<String>)> {
+        let mut assignments = Vec::new();
+        for stmt in suite {
+            let (targets, value) = match stmt {
+                Stmt::Assign(assign) => (assign.targets.iter().collect::<Vec<_>>(), &assign.value),
+                Stmt::AnnAssign(ast::StmtAnnAssign {
+                    target,
+                    value: Some(value),
+                    ..
+                }) => (vec![target.as_ref()], value),
+                _ => continue,
+            };
+            // Only a single name can alias a mark, as in `custom = pytest.mark.unit`
+            let marker = match targets.as_slice() {
+                [ast::Expr::Name(_)] => {
+                    mark_alias_target(&mask_strings_and_comments(self.source(value.as_ref())))
+                }
+                _ => None,
+            };
+            for target in targets {
+                if let ast::Expr::Name(name) = target {
+                    assignments.push((
+                        usize::from(stmt.start()),
+                        name.id.to_string(),
+                        marker.clone(),
+                    ));
+                }
+            }
+        }
+        assignments
+    }
+
+    /// The mark aliases bound at `offset` in the module, by name.
+    fn mark_aliases_at(&self, offset: usize) -> HashMap<String, String> {
+        let mut mark_aliases = HashMap::new();
+        for (start, name, marker) in &self.mark_aliases {
+            if *start >= offset {
+                break;
+            }
+            match marker {
+                Some(marker) => mark_aliases.insert(name.clone(), marker.clone()),
+                None => mark_aliases.remove(name),
+            };
+        }
+        mark_aliases
+    }
+
+    /// Number of cases the `parametrize` decorators expand a test into, if known statically.
+    fn parametrize_cases(&self, decorators: &[ast::Expr]) -> Option<usize> {
+        let decorators: Vec<String> = decorators
+            .iter()
+            .map(|decorator| mask_strings_and_comments(&format!("@{}", self.source(decorator))))
+            .collect();
+        parametrize_cases(decorators.iter().map(String::as_str))
+    }
+
+    /// Markers assigned to `pytestmark` directly within `body`.
+    fn pytestmark_markers(&self, body: &[Stmt]) -> HashSet<String> {
+        let mut markers = HashSet::new();
+
+        for stmt in body {
+            let value = match stmt {
+                Stmt::Assign(assign) if assign.targets.iter().any(is_pytestmark) => &assign.value,
+                Stmt::AnnAssign(ast::StmtAnnAssign {
+                    target,
+                    value: Some(value),
+                    ..
+                }) if is_pytestmark(target) => value,
+                _ => continue,
+            };
+            markers.extend(
+                extract_pytestmark_value_markers(self.source(value.as_ref()))
+                    .into_iter()
+                    .filter(|marker| !self.options.ignored_markers.contains(marker)),
+            );
+        }
+
+        markers
+    }
+
+    /// Whether one of the first statements of a function body calls `pytest.skip(...)`.
+    fn starts_with_skip(&self, body: &[Stmt]) -> bool {
+        body.iter().take(BODY_SKIP_STATEMENTS).any(|stmt| {
+            matches!(stmt, Stmt::Expr(ast::StmtExpr { value, .. })
+                if matches!(value.as_ref(), ast::Expr::Call(call)
+                    if self.source(call.func.as_ref()) == "pytest.skip"))
+        })
+    }
+
+    fn source(&self, node: &impl Ranged) -> &str {
+        &self.content[node.range()]
+    }
+
+    /// 1-based line number where `node` starts
+    fn line_of(&self, node: &impl Ranged) -> usize {
+        let offset = usize::from(node.start());
+        self.line_starts.partition_point(|&start| start <= offset)
+    }
+}
+
+fn is_pytestmark(target: &ast::Expr) -> bool {
+    matches!(target, ast::Expr::Name(name) if name.id.as_str() == "pytestmark")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(tests: &[TestFunction]) -> Vec<&str> {
+        tests.iter().map(|t| t.name.as_str()).collect()
+    }
+
+    fn markers(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_matches_regex_parser() {
+        let content = r#"
+import pytest
+
+pytestmark = pytest.mark.component
+
+@pytest.mark.unit
+@pytest.mark.parametrize(
+    "arg1, arg2",
+    [
+        pytest.param("a", "b"),
+    ],
+)
+def test_multiline_decorator(arg1, arg2):
+    pass
+
+def test_unmarked():
+    pass
+
+@pytest.mark.integration
+class TestMarkedClass:
+    def test_method_in_marked_class(self):
+        pass
+
+class TestUnmarkedClass:
+    pytestmark = [pytest.mark.slow]
+
+    @pytest.mark.skip
+    def test_skipped_method(self):
+        pass
+
+    def test_unmarked_method(self):
+        pass
+"#;
+
+        for exclude_markers in [
+            markers(&["unit", "integration"]),
+            markers(&["skip"]),
+            markers(&["slow", "component"]),
+        ] {
+            assert_eq!(
+                find_python_test_functions(content, &exclude_markers, &ParseOptions::default())
+                    .unwrap(),
+                crate::find_python_test_functions(content, &exclude_markers),
+            );
+        }
+    }
+
+    #[test]
+    fn test_ignores_strings_and_comments() {
+        let content = r#"
+EXAMPLE = """
+def test_in_docstring():
+    pass
+"""
+
+# def test_commented_out():
+#     pass
+
+def test_real():
+    pass
+"#;
+
+        let result =
+            find_python_test_functions(content, &markers(&["unit"]), &ParseOptions::default())
+                .unwrap();
+
+        assert_eq!(names(&result), vec!["test_real"]);
+        assert_eq!(result[0].line, 10);
+    }
+
+    #[test]
+    fn test_multiline_signature_and_nested_classes() {
+        let content = r#"
+import pytest
+
+@pytest.mark.unit
+class TestOuter:
+    class TestInner:
+        def test_inherits_outer_marker(
+            self,
+        ):
+            pass
+
+class TestPlain:
+    class TestInner:
+        @pytest.mark.unit
+        def test_marked(
+            self,
+            value,
+        ):
+            pass
+
+        def test_unmarked(self):
+            pass
+"#;
+
+        let result =
+            find_python_test_functions(content, &markers(&["unit"]), &ParseOptions::default())
+                .unwrap();
+
+        assert_eq!(names(&result), vec!["test_unmarked"]);
+    }
+
+    #[test]
+    fn test_syntax_error() {
+        assert!(
+            find_python_test_functions(
+                "def test_x(:\n",
+                &markers(&["unit"]),
+                &ParseOptions::default()
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_include_testcase() {
+        let content = r#"
+import unittest
+
+class Helpers:
+    def test_helper_method(self):
+        pass
+
+class FooTests(unittest.TestCase):
+    def test_in_testcase(self):
+        pass
+"#;
+
+        let options = ParseOptions {
+            include_testcase: true,
+            ..ParseOptions::default()
+        };
+        for options in [ParseOptions::default(), options] {
+            assert_eq!(
+                find_python_test_functions(content, &markers(&["unit"]), &options).unwrap(),
+                crate::find_python_test_functions_with_options(
+                    content,
+                    &markers(&["unit"]),
+                    &options
+                ),
+            );
+        }
+    }
+
+    #[test]
+    fn test_async_test_functions() {
+        let content = r#"
+import pytest
+
+async def test_async_unmarked():
+    pass
+
+@pytest.mark.unit
+async def test_async_marked():
+    pass
+
+class TestAsync:
+    async  def test_async_method(self):
+        pass
+"#;
+
+        let result =
+            find_python_test_functions(content, &markers(&["unit"]), &ParseOptions::default())
+                .unwrap();
+
+        assert_eq!(
+            result,
+            crate::find_python_test_functions(content, &markers(&["unit"]))
+        );
+        assert_eq!(
+            names(&result),
+            vec!["test_async_unmarked", "test_async_method"]
+        );
+    }
+
+    #[test]
+    fn test_nested_class_markers() {
+        let content = r#"
+import pytest
+
+class TestPlain:
+    @pytest.mark.integration
+    class TestMarkedInner:
+        class TestInnermost:
+            def test_inherits_inner_marker(self):
+                pass
+
+    def test_after_inner_class(self):
+        pass
+"#;
+
+        let exclude_markers = markers(&["integration"]);
+        let result =
+            find_python_test_functions(content, &exclude_markers, &ParseOptions::default())
+                .unwrap();
+
+        assert_eq!(
+            result,
+            crate::find_python_test_functions(content, &exclude_markers)
+        );
+        assert_eq!(names(&result), vec!["test_after_inner_class"]);
+    }
+
+    #[test]
+    fn test_fixtures_are_not_collected() {
+        let content = r#"
+import pytest
+
+@pytest.fixture(scope="module")
+def test_client():
+    pass
+
+class TestWithFixture:
+    @pytest.fixture
+    def test_data(self):
+        pass
+
+    def test_method(self, test_data):
+        pass
+"#;
+
+        let result =
+            find_python_test_functions(content, &HashSet::new(), &ParseOptions::default()).unwrap();
+
+        assert_eq!(
+            result,
+            crate::find_python_test_functions(content, &HashSet::new())
+        );
+        assert_eq!(names(&result), vec!["test_method"]);
+    }
+}
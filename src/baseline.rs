@@ -0,0 +1,103 @@
+//! Baseline files listing known unmarked tests that are not reported (`--baseline`).
+//!
+//! Each line holds one `path::test` entry such as `tests/test_x.py::TestFoo::test_a`;
+//! blank lines and lines starting with `#` are ignored. Line numbers are left out so
+//! that entries survive unrelated edits of the file.
+
+use collect_unmarked_tests::Finding;
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::path::{Component, Path};
+
+/// Read the entries of the baseline file at `path`.
+pub fn load(path: &Path) -> io::Result<HashSet<String>> {
+    Ok(parse(&std::fs::read_to_string(path)?))
+}
+
+fn parse(content: &str) -> HashSet<String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Remove the findings listed in `baseline`.
+pub fn apply(findings: &mut Vec<Finding>, baseline: &HashSet<String>) {
+    findings.retain(|finding| !baseline.contains(&entry(finding)));
+}
+
+/// Write a baseline listing all `findings`.
+pub fn write(out: &mut impl Write, findings: &[Finding]) -> io::Result<()> {
+    writeln!(
+        out,
+        "# Unmarked tests that are not reported, see --baseline"
+    )?;
+    let mut entries: Vec<String> = findings.iter().map(entry).collect();
+    entries.sort();
+    entries.dedup();
+    for entry in entries {
+        writeln!(out, "{}", entry)?;
+    }
+    Ok(())
+}
+
+/// Baseline entry of `finding`, with `/` separators on every platform so that baselines
+/// can be shared.
+fn entry(finding: &Finding) -> String {
+    let path: Vec<String> = finding
+        .file
+        .components()
+        .filter(|component| *component != Component::CurDir)
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    format!("{}::{}", path.join("/"), finding.qualified_name())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_finding as finding;
+
+    #[test]
+    fn test_write_and_apply_baseline() {
+        let known = [
+            finding("./tests/test_x.py", 1, &[], "test_b"),
+            finding("tests/test_x.py", 1, &["TestFoo"], "test_a"),
+        ];
+        let mut out = Vec::new();
+        write(&mut out, &known).unwrap();
+        let content = String::from_utf8(out).unwrap();
+
+        assert_eq!(
+            content,
+            "# Unmarked tests that are not reported, see --baseline\n\
+             tests/test_x.py::TestFoo::test_a\n\
+             tests/test_x.py::test_b\n"
+        );
+
+        let mut findings = vec![
+            finding("tests/test_x.py", 1, &[], "test_b"),
+            finding("tests/test_x.py", 1, &[], "test_a"),
+            finding("tests/test_y.py", 1, &[], "test_b"),
+        ];
+        apply(&mut findings, &parse(&content));
+
+        let remaining: Vec<String> = findings.iter().map(entry).collect();
+        assert_eq!(
+            remaining,
+            vec!["tests/test_x.py::test_a", "tests/test_y.py::test_b"]
+        );
+    }
+
+    #[test]
+    fn test_parse_skips_blank_lines_and_comments() {
+        let baseline = parse("# comment\n\n  tests/test_x.py::test_a  \r\n");
+
+        assert_eq!(
+            baseline,
+            HashSet::from(["tests/test_x.py::test_a".to_string()])
+        );
+    }
+}
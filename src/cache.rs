@@ -0,0 +1,126 @@
+//! On-disk cache of the tests found in each file, keyed by the file's size and
+//! modification time.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::{ParseOptions, ParserKind, TestFunction};
+
+const CACHE_FILE_NAME: &str = "scan-cache.json";
+
+/// Cached analysis results, shared by the threads analyzing files.
+pub(crate) struct Cache {
+    path: PathBuf,
+    key: String,
+    files: Mutex<HashMap<PathBuf, Entry>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    /// Results are only reused by runs with the same parser settings
+    key: String,
+    files: HashMap<PathBuf, Entry>,
+}
+
+/// Size and modification time of a file when it was analyzed.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Stamp {
+    size: u64,
+    modified: SystemTime,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    stamp: Stamp,
+    tests: Vec<TestFunction>,
+}
+
+impl Cache {
+    /// Load the cache from `dir`; a missing, unreadable or outdated cache starts empty.
+    pub(crate) fn load(dir: &Path, parser: ParserKind, options: &ParseOptions) -> Self {
+        let path = dir.join(CACHE_FILE_NAME);
+        let key = cache_key(parser, options);
+
+        let files = std::fs::read(&path)
+            .ok()
+            .and_then(|content| serde_json::from_slice::<CacheFile>(&content).ok())
+            .filter(|cache_file| cache_file.key == key)
+            .map(|cache_file| cache_file.files)
+            .unwrap_or_default();
+
+        Self {
+            path,
+            key,
+            files: Mutex::new(files),
+        }
+    }
+
+    /// Current size and modification time of `path`, if available.
+    pub(crate) fn stamp(path: &Path) -> Option<Stamp> {
+        let metadata = std::fs::metadata(path).ok()?;
+        Some(Stamp {
+            size: metadata.len(),
+            modified: metadata.modified().ok()?,
+        })
+    }
+
+    /// Tests found in `path` by an earlier run, if the file is unchanged since.
+    pub(crate) fn get(&self, path: &Path, stamp: Stamp) -> Option<Vec<TestFunction>> {
+        let files = self.files.lock().unwrap();
+        files
+            .get(path)
+            .filter(|entry| entry.stamp == stamp)
+            .map(|entry| entry.tests.clone())
+    }
+
+    pub(crate) fn insert(&self, path: &Path, stamp: Stamp, tests: &[TestFunction]) {
+        let entry = Entry {
+            stamp,
+            tests: tests.to_vec(),
+        };
+        self.files.lock().unwrap().insert(path.to_path_buf(), entry);
+    }
+
+    /// Write the cache back to disk, dropping entries of files that no longer exist.
+    pub(crate) fn save(self) -> std::io::Result<()> {
+        let mut files = self.files.into_inner().unwrap();
+        files.retain(|path, _| path.is_file());
+
+        let cache_file = CacheFile {
+            key: self.key,
+            files,
+        };
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&self.path, serde_json::to_vec(&cache_file)?)
+    }
+}
+
+/// Identify the parser settings that results were produced with.
+fn cache_key(parser: ParserKind, options: &ParseOptions) -> String {
+    let mut ignored_markers: Vec<&String> = options.ignored_markers.iter().collect();
+    ignored_markers.sort();
+    let mut marker_aliases: Vec<(&String, &String)> = options.marker_aliases.iter().collect();
+    marker_aliases.sort();
+
+    format!(
+        "{} {:?} {} {:?} {:?} {:?} {:?} {} {} {} {} {:?} {}",
+        env!("CARGO_PKG_VERSION"),
+        parser,
+        options.include_testcase,
+        options.test_prefixes,
+        options.class_prefixes,
+        ignored_markers,
+        marker_aliases,
+        options.tab_width,
+        options.skip_non_instance_methods,
+        options.param_marks,
+        options.strict_marks,
+        options.ignored_decorators,
+        options.detect_body_skips,
+    )
+}
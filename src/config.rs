@@ -0,0 +1,329 @@
+//! Configuration read from the `[tool.collect-unmarked-tests]` table of `pyproject.toml`,
+//! and the markers registered with pytest itself.
+
+use serde::Deserialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Settings from `pyproject.toml`; command-line flags take precedence over them.
+///
+/// Relative paths are resolved against the directory containing `pyproject.toml`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub exclude_markers: Option<Vec<String>>,
+    pub packages: Option<Vec<String>>,
+    pub test_dir: Option<PathBuf>,
+    pub cache_dir: Option<PathBuf>,
+}
+
+/// Markers pytest knows without registration.
+pub const PYTEST_BUILTIN_MARKERS: &[&str] = &[
+    "filterwarnings",
+    "parametrize",
+    "skip",
+    "skipif",
+    "usefixtures",
+    "xfail",
+];
+
+#[derive(Deserialize)]
+struct PyProject {
+    tool: Option<Tool>,
+}
+
+#[derive(Deserialize)]
+struct Tool {
+    #[serde(rename = "collect-unmarked-tests")]
+    collect_unmarked_tests: Option<Config>,
+    pytest: Option<PytestTool>,
+}
+
+#[derive(Deserialize)]
+struct PytestTool {
+    ini_options: Option<IniOptions>,
+}
+
+#[derive(Deserialize)]
+struct IniOptions {
+    #[serde(default)]
+    markers: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => write!(f, "failed to read {}: {}", path.display(), err),
+            ConfigError::Parse(path, err) => {
+                write!(f, "failed to parse {}: {}", path.display(), err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Load the configuration from the `pyproject.toml` nearest to the current directory.
+    ///
+    /// Only the nearest `pyproject.toml` is considered; `None` is returned if there is none
+    /// or it has no `[tool.collect-unmarked-tests]` table.
+    pub fn discover() -> Result<Option<Config>, ConfigError> {
+        let cwd =
+            std::env::current_dir().map_err(|err| ConfigError::Io(PathBuf::from("."), err))?;
+
+        // Build the path relative to the current directory, so configured paths show up
+        // in the output the same way as paths passed on the command line
+        let mut relative_dir = PathBuf::new();
+        for dir in cwd.ancestors() {
+            if dir.join("pyproject.toml").is_file() {
+                return Config::load(&relative_dir.join("pyproject.toml"));
+            }
+            relative_dir.push("..");
+        }
+
+        Ok(None)
+    }
+
+    /// Load the configuration from the given `pyproject.toml`.
+    pub fn load(path: &Path) -> Result<Option<Config>, ConfigError> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|err| ConfigError::Io(path.to_path_buf(), err))?;
+        let pyproject: PyProject =
+            toml::from_str(&content).map_err(|err| ConfigError::Parse(path.to_path_buf(), err))?;
+
+        let Some(mut config) = pyproject.tool.and_then(|tool| tool.collect_unmarked_tests) else {
+            return Ok(None);
+        };
+
+        let base_dir = path.parent().unwrap_or(Path::new(""));
+        if let Some(test_dir) = &mut config.test_dir {
+            *test_dir = base_dir.join(&test_dir);
+        }
+        if let Some(cache_dir) = &mut config.cache_dir {
+            *cache_dir = base_dir.join(&cache_dir);
+        }
+        if let Some(packages) = &mut config.packages {
+            for package in packages {
+                *package = base_dir.join(&package).to_string_lossy().into_owned();
+            }
+        }
+
+        Ok(Some(config))
+    }
+}
+
+/// Names of the markers registered in the nearest pytest configuration.
+///
+/// At each directory upwards from the current one, a `pytest.ini` is preferred over a
+/// `pyproject.toml` with a `[tool.pytest.ini_options]` table, as pytest does. Returns
+/// `None` if neither is found.
+pub fn discover_registered_markers() -> Result<Option<Vec<String>>, ConfigError> {
+    let cwd = std::env::current_dir().map_err(|err| ConfigError::Io(PathBuf::from("."), err))?;
+
+    for dir in cwd.ancestors() {
+        for file_name in ["pytest.ini", "pyproject.toml"] {
+            let path = dir.join(file_name);
+            if path.is_file()
+                && let Some(markers) = load_registered_markers(&path)?
+            {
+                return Ok(Some(markers));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Names of the markers registered in the given `pytest.ini` or `pyproject.toml`.
+///
+/// Returns `None` if the file has no pytest configuration.
+pub fn load_registered_markers(path: &Path) -> Result<Option<Vec<String>>, ConfigError> {
+    let content =
+        std::fs::read_to_string(path).map_err(|err| ConfigError::Io(path.to_path_buf(), err))?;
+
+    let markers = if path.extension().is_some_and(|ext| ext == "toml") {
+        let pyproject: PyProject =
+            toml::from_str(&content).map_err(|err| ConfigError::Parse(path.to_path_buf(), err))?;
+        pyproject
+            .tool
+            .and_then(|tool| tool.pytest)
+            .and_then(|pytest| pytest.ini_options)
+            .map(|ini_options| ini_options.markers)
+    } else {
+        ini_markers(&content)
+    };
+
+    // Entries look like `slow: marks tests as slow` or `serial(reason): ...`
+    Ok(markers.map(|markers| {
+        markers
+            .iter()
+            .filter_map(|entry| entry.split([':', '(']).next())
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .collect()
+    }))
+}
+
+/// Entries of the `markers` option in the `[pytest]` section of an ini file.
+fn ini_markers(content: &str) -> Option<Vec<String>> {
+    let mut markers = None;
+    let mut in_pytest_section = false;
+    let mut in_markers = false;
+
+    for line in content.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            in_pytest_section = trimmed == "[pytest]";
+            in_markers = false;
+            if in_pytest_section && markers.is_none() {
+                markers = Some(Vec::new());
+            }
+            continue;
+        }
+        if !in_pytest_section
+            || trimmed.is_empty()
+            || trimmed.starts_with('#')
+            || trimmed.starts_with(';')
+        {
+            continue;
+        }
+
+        // Indented lines continue the value of the previous option
+        if line.starts_with(char::is_whitespace) {
+            if in_markers && let Some(markers) = &mut markers {
+                markers.push(trimmed.to_string());
+            }
+            continue;
+        }
+
+        in_markers = false;
+        if let Some((key, value)) = trimmed.split_once(['=', ':'])
+            && key.trim() == "markers"
+            && let Some(markers) = &mut markers
+        {
+            in_markers = true;
+            if !value.trim().is_empty() {
+                markers.push(value.trim().to_string());
+            }
+        }
+    }
+
+    markers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pyproject.toml");
+        std::fs::write(
+            &path,
+            r#"
+[project]
+name = "example"
+
+[tool.collect-unmarked-tests]
+exclude_markers = ["unit", "e2e"]
+packages = ["src/a", "src/b"]
+test_dir = "tests"
+cache_dir = ".cache/collect-unmarked-tests"
+"#,
+        )
+        .unwrap();
+
+        let config = Config::load(&path).unwrap().unwrap();
+
+        assert_eq!(
+            config.exclude_markers,
+            Some(vec!["unit".to_string(), "e2e".to_string()])
+        );
+        assert_eq!(
+            config.packages,
+            Some(vec![
+                dir.path().join("src/a").to_string_lossy().into_owned(),
+                dir.path().join("src/b").to_string_lossy().into_owned(),
+            ])
+        );
+        assert_eq!(config.test_dir, Some(dir.path().join("tests")));
+        assert_eq!(
+            config.cache_dir,
+            Some(dir.path().join(".cache/collect-unmarked-tests"))
+        );
+    }
+
+    #[test]
+    fn test_load_without_table() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pyproject.toml");
+        std::fs::write(&path, "[tool.pytest.ini_options]\nmarkers = []\n").unwrap();
+
+        assert_eq!(Config::load(&path).unwrap(), None);
+    }
+
+    #[test]
+    fn test_load_rejects_unknown_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pyproject.toml");
+        std::fs::write(
+            &path,
+            "[tool.collect-unmarked-tests]\nexclude_marker = [\"unit\"]\n",
+        )
+        .unwrap();
+
+        assert!(matches!(Config::load(&path), Err(ConfigError::Parse(_, _))));
+    }
+
+    #[test]
+    fn test_load_registered_markers_from_pyproject() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pyproject.toml");
+        std::fs::write(
+            &path,
+            r#"
+[tool.pytest.ini_options]
+markers = [
+    "unit: fast isolated tests",
+    "serial(reason): tests that can't run in parallel",
+    "e2e",
+]
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            load_registered_markers(&path).unwrap(),
+            Some(vec![
+                "unit".to_string(),
+                "serial".to_string(),
+                "e2e".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn test_load_registered_markers_from_pytest_ini() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pytest.ini");
+        std::fs::write(
+            &path,
+            "[pytest]\naddopts = -ra\nmarkers =\n    unit: fast tests\n    slow\n\
+             testpaths = tests\n\n[other]\nmarkers = ignored\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            load_registered_markers(&path).unwrap(),
+            Some(vec!["unit".to_string(), "slow".to_string()])
+        );
+    }
+}
@@ -0,0 +1,165 @@
+//! Collect Python tests that don't carry specific pytest markers.
+//!
+//! The `collect-unmarked-tests` binary is a thin wrapper around [`Scanner`]. The parsing
+//! functions are exported as well, for tools that want to analyze source text directly.
+//!
+//! Files that can't be scanned are reported as [`ScanError`]s alongside the findings in
+//! [`ScanReport::skipped`]; use [`Scanner::try_scan`] to treat them as errors instead.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+mod archive;
+pub mod ast_parser;
+mod cache;
+mod config;
+mod parser;
+mod scanner;
+
+pub use archive::is_archive;
+pub use config::{
+    Config, ConfigError, PYTEST_BUILTIN_MARKERS, discover_registered_markers,
+    load_registered_markers,
+};
+pub use parser::{
+    collect_python_tests, collect_python_tests_from_reader, extract_pytest_marker,
+    extract_pytest_markers, find_python_test_functions, find_python_test_functions_with_options,
+};
+pub use scanner::{
+    DEFAULT_STREAM_THRESHOLD, Finding, MarkerSource, MarkerStats, ParserKind, ScanError,
+    ScanReport, Scanner,
+};
+
+/// Markers excluded when none are configured explicitly.
+pub const DEFAULT_EXCLUDE_MARKERS: &[&str] = &["unit", "integration", "component", "skip", "slow"];
+
+/// Marker names that don't categorize a test, ignored unless configured otherwise.
+pub const DEFAULT_IGNORED_MARKERS: &[&str] = &["parametrize", "usefixtures", "filterwarnings"];
+
+/// A test function found in Python source.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestFunction {
+    pub name: String,
+    /// Names of the enclosing test classes, outermost first
+    pub class_path: Vec<String>,
+    /// 1-based line number of the `def` line
+    pub line: usize,
+    /// Markers applied to the test by its decorators, enclosing classes and `pytestmark`
+    pub markers: BTreeSet<String>,
+    /// The same markers, by the scope that applies them
+    pub marker_sources: MarkerSources,
+    /// Number of cases the test's `parametrize` decorators expand it into, if they are
+    /// list or tuple literals that can be counted without running the code
+    #[serde(default)]
+    pub cases: Option<usize>,
+}
+
+/// Markers applied to a test, by the scope that applies them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MarkerSources {
+    /// Applied by a module-level `pytestmark`
+    pub module: BTreeSet<String>,
+    /// Applied by the decorators or `pytestmark` of enclosing classes
+    pub class: BTreeSet<String>,
+    /// Applied by the test's own decorators
+    pub function: BTreeSet<String>,
+}
+
+impl TestFunction {
+    pub(crate) fn new(
+        name: String,
+        class_path: Vec<String>,
+        line: usize,
+        marker_sources: MarkerSources,
+        cases: Option<usize>,
+    ) -> Self {
+        let markers = marker_sources
+            .module
+            .iter()
+            .chain(&marker_sources.class)
+            .chain(&marker_sources.function)
+            .cloned()
+            .collect();
+
+        Self {
+            name,
+            class_path,
+            line,
+            markers,
+            marker_sources,
+            cases,
+        }
+    }
+
+    /// Whether the test carries any of `markers`.
+    pub fn has_any_marker(&self, markers: &HashSet<String>) -> bool {
+        self.markers.iter().any(|marker| markers.contains(marker))
+    }
+}
+
+/// Options controlling which functions are recognized as tests.
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    /// Also treat `TestCase` subclasses whose name doesn't start with one of
+    /// `class_prefixes` as test classes
+    pub include_testcase: bool,
+    /// Name prefixes of test functions, matched literally
+    pub test_prefixes: Vec<String>,
+    /// Name prefixes of test classes, matched literally; methods of other classes are
+    /// collected too, but the markers of those classes never apply
+    pub class_prefixes: Vec<String>,
+    /// Marker names that never count as categorizing a test
+    pub ignored_markers: HashSet<String>,
+    /// Marker names counted as another marker, e.g. `skipif` as `skip`
+    pub marker_aliases: HashMap<String, String>,
+    /// Number of columns a tab advances indentation to the next multiple of
+    pub tab_width: usize,
+    /// Skip test methods decorated with `@staticmethod` or `@classmethod`, which pytest
+    /// collects as well
+    pub skip_non_instance_methods: bool,
+    /// Count markers that a `parametrize` decorator applies to every one of its cases via
+    /// `pytest.param(..., marks=...)` as markers of the test
+    pub param_marks: bool,
+    /// Only count `@pytest.mark.*` and `@mark.*` decorators as markers, not arbitrary
+    /// decorators such as `@requires_db`
+    pub strict_marks: bool,
+    /// Dotted names of decorators that never apply markers, e.g. `mock.patch`
+    pub ignored_decorators: Vec<String>,
+    /// Count a `pytest.skip(...)` call among the first top-level statements of a test body
+    /// as a `skip` marker
+    pub detect_body_skips: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            include_testcase: false,
+            test_prefixes: vec!["test_".to_string()],
+            class_prefixes: vec!["Test".to_string()],
+            ignored_markers: DEFAULT_IGNORED_MARKERS
+                .iter()
+                .map(|marker| marker.to_string())
+                .collect(),
+            marker_aliases: HashMap::new(),
+            tab_width: 8,
+            skip_non_instance_methods: false,
+            param_marks: false,
+            strict_marks: false,
+            ignored_decorators: Vec::new(),
+            detect_body_skips: false,
+        }
+    }
+}
+
+impl ParseOptions {
+    /// Apply `marker_aliases` to the markers found on a test.
+    pub(crate) fn resolve_markers(
+        &self,
+        markers: impl IntoIterator<Item = String>,
+    ) -> BTreeSet<String> {
+        markers
+            .into_iter()
+            .map(|marker| self.marker_aliases.get(&marker).cloned().unwrap_or(marker))
+            .collect()
+    }
+}
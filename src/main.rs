@@ -1,409 +1,1448 @@
-use clap::Parser;
+mod baseline;
+mod output;
+mod watch;
+
+use anstream::{AutoStream, StripStream};
+use clap::{ColorChoice, Parser, ValueEnum};
+use collect_unmarked_tests::{
+    Config, DEFAULT_EXCLUDE_MARKERS, DEFAULT_STREAM_THRESHOLD, Finding, MarkerSource,
+    PYTEST_BUILTIN_MARKERS, ParseOptions, ParserKind, Scanner, discover_registered_markers,
+    is_archive,
+};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use indicatif::{ProgressBar, ProgressStyle};
+use output::{OutputFormat, OutputTemplate, SortKey};
 use regex::Regex;
-use std::collections::HashSet;
-use std::path::PathBuf;
-use walkdir::WalkDir;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, IsTerminal, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 #[derive(Parser)]
 #[command(name = "collect-unmarked-tests")]
 #[command(about = "Collect Python tests that don't have specific markers")]
+#[command(
+    after_help = "Defaults for --exclude-markers, --packages, --cache-dir and the test directory can \
+                  be set in the [tool.collect-unmarked-tests] table of the nearest pyproject.toml.\n\n\
+                  The markers to exclude and the test directory can also be set with the \
+                  COLLECT_UNMARKED_EXCLUDE_MARKERS (comma-separated) and COLLECT_UNMARKED_TEST_DIR \
+                  environment variables. Command-line arguments take precedence over the \
+                  environment, the environment over pyproject.toml, and pyproject.toml over the \
+                  built-in defaults."
+)]
 struct Args {
-    /// Test directory to scan
-    #[arg(default_value = "tests")]
-    test_dir: PathBuf,
-
-    /// Markers to exclude (default: unit,integration,component,skip,slow)
-    #[arg(long, value_delimiter = ',')]
+    /// Test directories, Python files or .tar, .tar.gz, .zip and .whl archives to scan
+    /// [env: COLLECT_UNMARKED_TEST_DIR] [default: tests]
+    #[arg(value_name = "TEST_DIR")]
+    test_dirs: Vec<PathBuf>,
+
+    /// Markers to exclude [env: COLLECT_UNMARKED_EXCLUDE_MARKERS]
+    /// (default: unit,integration,component,skip,slow)
+    #[arg(long, value_delimiter = ',', value_parser = parse_marker)]
     exclude_markers: Option<Vec<String>>,
 
-    /// Whitelisted package modules to scan (for monorepo support)
+    /// Only count markers applied at this level when deciding whether a test is excluded
+    #[arg(long, value_enum, default_value_t = MarkerSource::All)]
+    marker_source: MarkerSource,
+
+    /// Report tests carrying fewer than N distinct markers, whichever they are
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with_all = [
+            "exclude_markers", "add_exclude_markers", "require_markers", "markers_from_ini",
+            "exclude_marker_regex", "verbose",
+        ]
+    )]
+    min_markers: Option<usize>,
+
+    /// Report only tests without any marker on the function, its classes or the module
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "min_markers", "exclude_markers", "add_exclude_markers", "require_markers",
+            "markers_from_ini", "exclude_marker_regex", "marker_source", "verbose",
+        ]
+    )]
+    no_markers_only: bool,
+
+    /// Also exclude markers matching this regex, e.g. '^feature_' (repeatable)
+    #[arg(long, value_name = "REGEX")]
+    exclude_marker_regex: Vec<Regex>,
+
+    /// Markers to exclude in addition to the default or configured ones
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_name = "MARKERS",
+        value_parser = parse_marker,
+        conflicts_with = "require_markers"
+    )]
+    add_exclude_markers: Vec<String>,
+
+    /// Exclude every marker registered in the pytest configuration, and pytest's builtin ones
+    #[arg(long, conflicts_with_all = ["exclude_markers", "require_markers"])]
+    markers_from_ini: bool,
+
+    /// Warn about markers to exclude or require that aren't registered with pytest
+    #[arg(long)]
+    check_markers: bool,
+
+    /// Markers of which every test must carry at least one (no defaults)
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_parser = parse_marker,
+        conflicts_with = "exclude_markers"
+    )]
+    require_markers: Option<Vec<String>>,
+
+    /// Lowercase the markers to exclude or require, e.g. when given as `Unit,SLOW`
+    #[arg(long)]
+    lowercase_markers: bool,
+
+    /// Whitelisted package modules to scan (for monorepo support); globs such as
+    /// `packages/*/tests` are expanded
     #[arg(long, value_delimiter = ',')]
     packages: Option<Vec<String>>,
+
+    /// Read the files to scan from stdin, one path per line, instead of walking directories
+    #[arg(long, conflicts_with_all = ["test_dirs", "packages"])]
+    stdin: bool,
+
+    /// Keep running and report again whenever Python files in the scanned directories change
+    #[arg(long, conflicts_with_all = ["stdin", "output", "quiet"])]
+    watch: bool,
+
+    /// Only list the Python files that would be scanned, after all filters, and exit
+    #[arg(long, conflicts_with_all = ["stdin", "watch"])]
+    dry_run: bool,
+
+    /// Parser used to find test functions and their decorators
+    #[arg(long, value_enum, default_value_t = ParserKind::Regex)]
+    parser: ParserKind,
+
+    /// Also apply the markers of unittest.TestCase subclasses without a class prefix
+    #[arg(long)]
+    include_testcase: bool,
+
+    /// Name prefix of test functions, matched literally (repeatable)
+    #[arg(long = "test-prefix", value_name = "PREFIX", default_value = "test_")]
+    test_prefixes: Vec<String>,
+
+    /// Name prefix of test classes, matched literally (repeatable)
+    #[arg(long = "class-prefix", value_name = "PREFIX", default_value = "Test")]
+    class_prefixes: Vec<String>,
+
+    /// Marker names that never count as categorizing a test
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "parametrize,usefixtures,filterwarnings"
+    )]
+    ignore_marker_names: Vec<String>,
+
+    /// Count a marker as another one, e.g. `skipif=skip` (repeatable)
+    #[arg(long, value_name = "FROM=TO", value_parser = parse_marker_alias)]
+    marker_alias: Vec<(String, String)>,
+
+    /// Whether @pytest.mark.skipif counts as skip (true), or never categorizes a test (false);
+    /// by default it is a marker of its own
+    #[arg(
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    skipif_counts_as_skip: Option<bool>,
+
+    /// Don't report test methods decorated with @staticmethod or @classmethod
+    #[arg(long)]
+    skip_non_instance_methods: bool,
+
+    /// Count markers that every case of a parametrize decorator gets via pytest.param(marks=...)
+    #[arg(long)]
+    param_marks: bool,
+
+    /// Only count @pytest.mark.* and @mark.* decorators as markers, not e.g. @requires_db
+    #[arg(long)]
+    strict_marks: bool,
+
+    /// Decorators that never apply markers, e.g. `mock.patch,freeze_time`
+    #[arg(long, value_delimiter = ',', value_name = "DECORATORS", value_parser = parse_decorator)]
+    ignore_decorators: Vec<String>,
+
+    /// Treat tests calling pytest.skip(...) among the first statements of their body as skipped
+    #[arg(long)]
+    detect_body_skips: bool,
+
+    /// Number of columns a tab indents to the next multiple of (regex parser)
+    #[arg(long, value_name = "N", default_value = "8")]
+    tab_width: NonZeroUsize,
+
+    /// Read files larger than this many bytes line by line instead of at once (regex parser)
+    #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_STREAM_THRESHOLD)]
+    stream_threshold: u64,
+
+    /// Extension of the files to scan, e.g. `pyx` for Cython tests (repeatable)
+    #[arg(
+        long = "extension",
+        value_name = "EXT",
+        default_value = "py",
+        value_parser = parse_extension
+    )]
+    extensions: Vec<String>,
+
+    /// Also scan files excluded by .gitignore, .ignore and global git excludes
+    #[arg(long)]
+    no_ignore: bool,
+
+    /// Only scan files whose path relative to the scanned directory matches this glob
+    /// (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    include_path: Vec<Glob>,
+
+    /// Skip files whose path relative to the scanned directory matches this glob (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    exclude_path: Vec<Glob>,
+
+    /// Skip files whose name matches this glob, wherever they are located (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    skip_file_glob: Vec<Glob>,
+
+    /// Skip test methods of classes whose name matches this glob, e.g. 'TestBase' (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    exclude_classes: Vec<Glob>,
+
+    /// Descend at most N levels into each directory; 1 scans only the files directly in it
+    #[arg(long, value_name = "N")]
+    max_depth: Option<usize>,
+
+    /// Descend into symlinked directories; symlink loops are reported as warnings
+    #[arg(long)]
+    follow_symlinks: bool,
+
+    /// Cache the tests found in each file here and reuse them while the file is unchanged
+    #[arg(long, value_name = "DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// Don't use a cache, even if `cache_dir` is configured in pyproject.toml
+    #[arg(long, conflicts_with = "cache_dir")]
+    no_cache: bool,
+
+    /// Output format for the findings
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Print each finding as a line of this template to stdout instead, with the placeholders
+    /// {file}, {line}, {class}, {func} and {markers}, e.g. '{file}:{line}: {func}'
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        conflicts_with_all = ["format", "group_by_file", "count"]
+    )]
+    output_template: Option<OutputTemplate>,
+
+    /// List each file once with its unmarked tests indented beneath it (text format)
+    #[arg(long)]
+    group_by_file: bool,
+
+    /// Print only the number of unmarked tests to stdout
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "format", "group_by_file", "summary", "stats", "verbose", "output", "quiet", "watch",
+        ]
+    )]
+    count: bool,
+
+    /// Also print the number of unmarked tests per file, most affected files first
+    #[arg(long)]
+    summary: bool,
+
+    /// Also print how many tests carry each marker to stdout
+    #[arg(long)]
+    stats: bool,
+
+    /// Fail with code 1 if unmarked tests are found (the default), printing only their
+    /// number instead of listing them
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "list", "exit_zero", "format", "output_template", "group_by_file", "output"
+        ]
+    )]
+    check: bool,
+
+    /// Only list the unmarked tests for inspection; always exit with code 0 on findings
+    #[arg(long, conflicts_with_all = ["exit_zero", "fail_under", "quiet", "count"])]
+    list: bool,
+
+    /// Stop at the first unmarked test found, report only it and exit with code 1
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "list", "exit_zero", "fail_under", "baseline", "write_baseline", "stats", "watch"
+        ]
+    )]
+    fail_fast: bool,
+
+    /// Exit with code 0 even if unmarked tests are found, e.g. for reporting only
+    #[arg(long)]
+    exit_zero: bool,
+
+    /// Exit with code 1 only if more than N unmarked tests are found
+    #[arg(long, value_name = "N", conflicts_with = "exit_zero")]
+    fail_under: Option<usize>,
+
+    /// Exit with code 2 if any file could not be read or parsed
+    #[arg(long)]
+    strict: bool,
+
+    /// Don't report the known unmarked tests listed in this file, one `path::test` per line
+    #[arg(long, value_name = "PATH")]
+    baseline: Option<PathBuf>,
+
+    /// Write all current findings to this baseline file instead of reporting them
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["watch", "output"])]
+    write_baseline: Option<PathBuf>,
+
+    /// Write the findings to this file instead of stderr/stdout
+    #[arg(long, short, value_name = "PATH", required_if_eq("format", "junit"))]
+    output: Option<PathBuf>,
+
+    /// When to color the human-readable output
+    #[arg(long, value_name = "WHEN", default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    /// Show a spinner counting the scanned files on stderr, if it is a terminal
+    #[arg(long)]
+    progress: bool,
+
+    /// Warn on stderr about tests defined more than once in the same scope of a file
+    #[arg(long)]
+    warn_duplicates: bool,
+
+    /// Finish with a JSON line like `{"found": 14, "scanned_files": 1203}` on stdout
+    #[arg(long, conflicts_with_all = ["count", "watch", "quiet"])]
+    summary_json: bool,
+
+    /// Print the effective configuration to stderr and exit without scanning
+    #[arg(long, conflicts_with_all = ["dry_run", "watch"])]
+    print_config: bool,
+
+    /// Print the elapsed wall time and the number of files scanned per second on stderr
+    #[arg(long)]
+    time: bool,
+
+    /// Explain on stderr which markers were found for every test and why it was reported
+    #[arg(long, short)]
+    verbose: bool,
+
+    /// Order in which findings are reported
+    #[arg(long, value_enum, default_value_t = SortKey::Path)]
+    sort: SortKey,
+
+    /// Report paths relative to this directory where possible [default: current directory]
+    #[arg(long, value_name = "DIR", num_args = 0..=1, default_missing_value = ".")]
+    relative_to: Option<PathBuf>,
+
+    /// Report paths with `/` separators, also on Windows
+    #[arg(long)]
+    posix_paths: bool,
+
+    /// Print nothing; only report the result through the exit code
+    #[arg(long, short)]
+    quiet: bool,
+
+    /// Number of threads used to scan files (default: number of CPUs)
+    #[arg(long)]
+    jobs: Option<usize>,
 }
 
+/// Comma-separated markers to exclude when `--exclude-markers` isn't passed
+const EXCLUDE_MARKERS_ENV: &str = "COLLECT_UNMARKED_EXCLUDE_MARKERS";
+/// Directory to scan when no test directories are passed
+const TEST_DIR_ENV: &str = "COLLECT_UNMARKED_TEST_DIR";
+
+/// No unmarked tests found
+const EXIT_CLEAN: i32 = 0;
+/// Unmarked tests found
+const EXIT_FINDINGS: i32 = 1;
+/// Invalid usage or configuration, I/O errors or internal errors, so that CI can tell a
+/// failure of the tool from a policy failure
+const EXIT_ERROR: i32 = 2;
+
 fn main() {
+    let start = Instant::now();
+    // Invalid arguments exit with 2 as well, as clap does by default
     let args = Args::parse();
 
-    let default_markers = vec![
-        "unit".to_string(),
-        "integration".to_string(),
-        "component".to_string(),
-        "skip".to_string(),
-        "slow".to_string(),
-    ];
-
-    let exclude_markers: HashSet<String> = args
-        .exclude_markers
-        .unwrap_or(default_markers)
-        .into_iter()
+    // A crash must not look like unmarked tests, nor like a clean run
+    let default_panic_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_panic_hook(info);
+        std::process::exit(EXIT_ERROR);
+    }));
+
+    if let Some(jobs) = args.jobs {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build_global()
+            .expect("global thread pool is only initialized once");
+    }
+
+    let config = match Config::discover() {
+        Ok(config) => config.unwrap_or_default(),
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(EXIT_ERROR);
+        }
+    };
+
+    let default_markers = DEFAULT_EXCLUDE_MARKERS
+        .iter()
+        .map(|marker| marker.to_string())
         .collect();
 
-    let unmarked_tests = if let Some(packages) = &args.packages {
-        collect_unmarked_tests_for_packages(packages, &exclude_markers)
+    let mode = if args.no_markers_only {
+        "report tests without any marker".to_string()
+    } else if let Some(min_markers) = args.min_markers {
+        format!("report tests with fewer than {} markers", min_markers)
+    } else if args.require_markers.is_some() {
+        "report tests without a required marker".to_string()
     } else {
-        collect_unmarked_tests(&args.test_dir, &exclude_markers)
+        "report tests without an excluded marker".to_string()
     };
 
-    if unmarked_tests.is_empty() {
-        println!("No unmarked tests found.");
+    // A test passes when it carries any of the given markers, so requiring a set of
+    // markers is the same check as excluding it; only the defaults differ.
+    let (markers, markers_origin) = if let Some(markers) = args.require_markers {
+        (markers, "--require-markers")
+    } else if let Some(markers) = args.exclude_markers {
+        (markers, "--exclude-markers")
+    } else if args.markers_from_ini {
+        (known_pytest_markers(), "--markers-from-ini")
+    } else if let Some(value) = env_var(EXCLUDE_MARKERS_ENV) {
+        // Only parsed when no flag overrides it, so that a bad value can't get in the way
+        let markers = parse_markers(&value).unwrap_or_else(|err| {
+            eprintln!("error: invalid {}: {}", EXCLUDE_MARKERS_ENV, err);
+            std::process::exit(EXIT_ERROR);
+        });
+        (markers, EXCLUDE_MARKERS_ENV)
+    } else if let Some(markers) = config.exclude_markers {
+        (markers, "pyproject.toml")
     } else {
-        eprintln!("Found {} unmarked test(s):", unmarked_tests.len());
-        for test in &unmarked_tests {
-            eprintln!("  {}", test);
+        (default_markers, "built-in defaults")
+    };
+    let exclude_markers: HashSet<String> = markers
+        .into_iter()
+        .chain(args.add_exclude_markers)
+        .map(|marker| {
+            if args.lowercase_markers {
+                marker.to_lowercase()
+            } else {
+                marker
+            }
+        })
+        .collect();
+
+    let (scan_roots, directories_origin) = resolve_scan_roots(
+        args.packages,
+        args.test_dirs.clone(),
+        env_var(TEST_DIR_ENV),
+        config.packages,
+        config.test_dir,
+    );
+    // Print the settings before validating them, so that bad ones can be debugged
+    if args.print_config {
+        let mut markers: Vec<&str> = exclude_markers.iter().map(String::as_str).collect();
+        markers.sort_unstable();
+        let directories = if args.stdin {
+            "read from stdin".to_string()
+        } else {
+            let directories: Vec<String> = match &scan_roots {
+                ScanRoots::Packages(packages) => packages.clone(),
+                ScanRoots::Directories(directories) => directories
+                    .iter()
+                    .map(|directory| directory.display().to_string())
+                    .collect(),
+            };
+            format!("{} (from {})", directories.join(", "), directories_origin)
+        };
+        let exit = if args.list {
+            "--list (exit with 0 on findings)".to_string()
+        } else if args.exit_zero {
+            "--exit-zero (exit with 0 on findings)".to_string()
+        } else if let Some(fail_under) = args.fail_under {
+            format!("--fail-under {} (exit with 1 above it)", fail_under)
+        } else {
+            "--check (exit with 1 on findings)".to_string()
+        };
+        let format = match &args.output_template {
+            Some(_) => "custom --output-template".to_string(),
+            None => value_name(args.format),
+        };
+        let cache_dir = match args.cache_dir.as_ref().or(config.cache_dir.as_ref()) {
+            Some(cache_dir) if !args.no_cache => cache_dir.display().to_string(),
+            _ => "none".to_string(),
+        };
+
+        let markers_key = if markers_origin == "--require-markers" {
+            "required markers"
+        } else {
+            "exclude markers"
+        };
+        let patterns: Vec<&str> = args
+            .exclude_marker_regex
+            .iter()
+            .map(Regex::as_str)
+            .collect();
+        // Counting markers doesn't depend on which ones are excluded
+        let markers = if args.min_markers.is_some() || args.no_markers_only {
+            String::new()
+        } else {
+            format!("{} (from {})", markers.join(", "), markers_origin)
+        };
+
+        let entries = [
+            ("mode", mode),
+            (markers_key, markers),
+            ("marker patterns", patterns.join(", ")),
+            ("marker source", value_name(args.marker_source)),
+            ("directories", directories),
+            ("parser", value_name(args.parser)),
+            ("format", format),
+            ("exit code", exit),
+            ("cache dir", cache_dir),
+        ];
+        if let Err(err) = write_config(&mut std::io::stderr(), &entries) {
+            eprintln!("error: failed to write output: {}", err);
+            std::process::exit(EXIT_ERROR);
         }
-        std::process::exit(1);
+        return;
     }
-}
 
-fn collect_unmarked_tests_for_packages(
-    packages: &[String],
-    exclude_markers: &HashSet<String>,
-) -> Vec<String> {
-    let mut unmarked_tests = Vec::new();
+    if args.check_markers {
+        check_registered_markers(&exclude_markers);
+    }
 
-    for package in packages {
-        let package_dir = PathBuf::from(package);
-        if package_dir.exists() {
-            unmarked_tests.extend(collect_unmarked_tests(&package_dir, exclude_markers));
+    let include_paths = (!args.include_path.is_empty()).then(|| build_glob_set(args.include_path));
+    let mut ignored_markers: HashSet<String> = args.ignore_marker_names.into_iter().collect();
+    let mut marker_aliases: HashMap<String, String> = args.marker_alias.into_iter().collect();
+    match args.skipif_counts_as_skip {
+        Some(true) => {
+            marker_aliases.insert("skipif".to_string(), "skip".to_string());
         }
+        // Conditionally skipped tests are reported like unmarked ones
+        Some(false) => {
+            ignored_markers.insert("skipif".to_string());
+        }
+        None => {}
     }
 
-    unmarked_tests
-}
-
-fn collect_unmarked_tests(test_dir: &PathBuf, exclude_markers: &HashSet<String>) -> Vec<String> {
-    let mut unmarked_tests = Vec::new();
+    let exclude_paths = build_glob_set(args.exclude_path);
+    let skip_file_names = build_glob_set(args.skip_file_glob);
+    let exclude_classes = build_glob_set(args.exclude_classes);
 
-    for entry in WalkDir::new(test_dir)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.path().extension().is_some_and(|ext| ext == "py"))
-    {
-        if let Ok(content) = std::fs::read_to_string(entry.path()) {
-            let tests = find_python_test_functions(&content, exclude_markers);
-            for test in tests {
-                unmarked_tests.push(format!("{}::{}", entry.path().display(), test));
-            }
+    for path in &args.test_dirs {
+        if !path.exists() {
+            eprintln!("error: {}: no such file or directory", path.display());
+            std::process::exit(EXIT_ERROR);
+        }
+        if path.is_file()
+            && !is_archive(path)
+            && path.extension().is_none_or(|ext| {
+                !args
+                    .extensions
+                    .iter()
+                    .any(|extension| ext == extension.as_str())
+            })
+        {
+            eprintln!(
+                "error: {}: not a Python file or archive (see --extension)",
+                path.display()
+            );
+            std::process::exit(EXIT_ERROR);
         }
     }
 
-    unmarked_tests
-}
+    let directories = match scan_roots {
+        ScanRoots::Packages(packages) => {
+            expand_packages(&packages, args.quiet).unwrap_or_else(|err| {
+                eprintln!("error: {}", err);
+                std::process::exit(EXIT_ERROR);
+            })
+        }
+        ScanRoots::Directories(directories) => directories,
+    };
 
-fn find_python_test_functions(content: &str, exclude_markers: &HashSet<String>) -> Vec<String> {
-    let mut test_functions = Vec::new();
-
-    // Regex to match test functions (allow whitespace at start)
-    let test_fn_regex = Regex::new(r"^(\s*)def\s+(test_\w+)\s*\(").unwrap();
-    // Regex to match class definitions
-    let class_regex = Regex::new(r"^(\s*)class\s+(\w+)").unwrap();
-
-    let lines: Vec<&str> = content.lines().collect();
-
-    // Track class-level markers
-    let mut class_markers: Vec<(usize, HashSet<String>)> = Vec::new(); // (indent_level, markers)
-
-    for (i, line) in lines.iter().enumerate() {
-        // Check for class definitions and their markers
-        if let Some(captures) = class_regex.captures(line) {
-            let class_indent = captures.get(1).unwrap().as_str().len();
-            let mut class_level_markers = HashSet::new();
-
-            // Look backwards for class-level decorators
-            let mut j = i;
-            let mut brace_depth = 0;
-            let mut paren_depth = 0;
-            let mut bracket_depth = 0;
-
-            while j > 0 {
-                j -= 1;
-                let prev_line = lines[j];
-                let trimmed = prev_line.trim();
-
-                if trimmed.is_empty() {
-                    continue;
-                }
-
-                // Count braces, parentheses, and brackets
-                for ch in trimmed.chars() {
-                    match ch {
-                        '(' => paren_depth += 1,
-                        ')' => paren_depth -= 1,
-                        '[' => bracket_depth += 1,
-                        ']' => bracket_depth -= 1,
-                        '{' => brace_depth += 1,
-                        '}' => brace_depth -= 1,
-                        _ => {}
-                    }
-                }
-
-                if trimmed.starts_with('@') {
-                    if let Some(marker) = extract_pytest_marker(trimmed) {
-                        class_level_markers.insert(marker);
-                    }
-                    if brace_depth == 0 && paren_depth == 0 && bracket_depth == 0 {
-                        // Continue to look for more decorators
-                    }
-                } else if brace_depth == 0 && paren_depth == 0 && bracket_depth == 0 {
-                    break;
-                }
+    let relative_to = args
+        .relative_to
+        .as_deref()
+        .map(|base| match std::path::absolute(base) {
+            Ok(base) => base,
+            Err(err) => {
+                eprintln!("error: invalid --relative-to {}: {}", base.display(), err);
+                std::process::exit(EXIT_ERROR);
             }
+        });
+
+    let baseline = match &args.baseline {
+        Some(path) => match baseline::load(path) {
+            Ok(baseline) => baseline,
+            Err(err) => {
+                eprintln!("error: failed to read {}: {}", path.display(), err);
+                std::process::exit(EXIT_ERROR);
+            }
+        },
+        None => HashSet::new(),
+    };
 
-            // Remove any previous class markers at same or deeper indentation
-            class_markers.retain(|(indent, _)| *indent < class_indent);
+    let scanner = Scanner::new(exclude_markers)
+        .exclude_marker_patterns(args.exclude_marker_regex)
+        .marker_source(args.marker_source)
+        // A test without markers is one with fewer than one
+        .min_markers(args.min_markers.or(args.no_markers_only.then_some(1)))
+        .fail_fast(args.fail_fast)
+        .directories(directories.clone())
+        .parser(args.parser)
+        .parse_options(ParseOptions {
+            include_testcase: args.include_testcase,
+            test_prefixes: args.test_prefixes,
+            class_prefixes: args.class_prefixes,
+            ignored_markers,
+            marker_aliases,
+            tab_width: args.tab_width.get(),
+            skip_non_instance_methods: args.skip_non_instance_methods,
+            param_marks: args.param_marks,
+            strict_marks: args.strict_marks,
+            ignored_decorators: args.ignore_decorators,
+            detect_body_skips: args.detect_body_skips,
+        })
+        .respect_ignore_files(!args.no_ignore)
+        .extensions(args.extensions)
+        .exclude_paths(exclude_paths)
+        .skip_file_names(skip_file_names)
+        .exclude_classes(exclude_classes)
+        .max_depth(args.max_depth)
+        .follow_symlinks(args.follow_symlinks)
+        .stream_threshold(args.stream_threshold)
+        .cache_dir(if args.no_cache {
+            None
+        } else {
+            args.cache_dir.or(config.cache_dir)
+        });
+    let scanner = match include_paths {
+        Some(include_paths) => scanner.include_paths(include_paths),
+        None => scanner,
+    };
 
-            // Add this class's markers if any
-            if !class_level_markers.is_empty() {
-                class_markers.push((class_indent, class_level_markers));
+    if args.dry_run {
+        let (files, skipped) = scanner.files();
+        if !args.quiet {
+            for file in &files {
+                println!("{}", file.display());
             }
-            continue;
+            for skipped in &skipped {
+                eprintln!("warning: skipping {}", skipped);
+            }
+            eprintln!("{} file(s) would be scanned", files.len());
         }
+        return;
+    }
 
-        if let Some(captures) = test_fn_regex.captures(line) {
-            let function_name = captures.get(2).unwrap().as_str();
-            let function_indent = captures.get(1).unwrap().as_str().len();
-
-            // Check if this function is in a class with excluded markers
-            let mut has_excluded_marker = false;
-            for (class_indent, markers) in &class_markers {
-                if function_indent > *class_indent {
-                    // This function is inside this class
-                    for marker in markers {
-                        if exclude_markers.contains(marker) {
-                            has_excluded_marker = true;
-                            break;
-                        }
-                    }
-                    if has_excluded_marker {
-                        break;
-                    }
-                }
+    let progress =
+        (args.progress && !args.quiet && std::io::stderr().is_terminal()).then(progress_spinner);
+    let scanned_files = Arc::new(AtomicUsize::new(0));
+    let scanner = {
+        let scanned_files = Arc::clone(&scanned_files);
+        let progress = progress.clone();
+        scanner.on_file_scanned(move |_| {
+            scanned_files.fetch_add(1, Ordering::Relaxed);
+            if let Some(progress) = &progress {
+                progress.inc(1);
             }
+        })
+    };
 
-            // If not marked by class, check function-level decorators
-            if !has_excluded_marker {
-                // Start from the line before the function and work backwards
-                let mut j = i;
-                let mut brace_depth = 0;
-                let mut paren_depth = 0;
-                let mut bracket_depth = 0;
-
-                while j > 0 {
-                    j -= 1;
-                    let line = lines[j];
-                    let trimmed = line.trim();
-
-                    // Skip blank lines
-                    if trimmed.is_empty() {
-                        continue;
-                    }
-
-                    // Count braces, parentheses, and brackets to handle multi-line decorators
-                    for ch in trimmed.chars() {
-                        match ch {
-                            '(' => paren_depth += 1,
-                            ')' => paren_depth -= 1,
-                            '[' => bracket_depth += 1,
-                            ']' => bracket_depth -= 1,
-                            '{' => brace_depth += 1,
-                            '}' => brace_depth -= 1,
-                            _ => {}
-                        }
-                    }
-
-                    // If the line starts with @, it's a decorator
-                    if trimmed.starts_with('@') {
-                        if let Some(marker) = extract_pytest_marker(trimmed)
-                            && exclude_markers.contains(&marker)
-                        {
-                            has_excluded_marker = true;
-                            break;
-                        }
-                        // If we're at balanced braces/parens/brackets, this decorator is complete
-                        if brace_depth == 0 && paren_depth == 0 && bracket_depth == 0 {
-                            // Continue to look for more decorators
-                        }
-                    } else if brace_depth == 0 && paren_depth == 0 && bracket_depth == 0 {
-                        // We're not in a multi-line decorator and this isn't a decorator line
-                        // This means we've gone past all decorators for this function
-                        break;
-                    }
-                    // Otherwise, this is part of a multi-line decorator, keep going
-                }
-            }
+    let mut report = if args.stdin {
+        scanner.scan_files(&read_files_from_stdin(&scanner))
+    } else {
+        scanner.scan()
+    };
+    if let Some(progress) = progress {
+        progress.finish_and_clear();
+    }
+    // Rescans are matched to the files as the scanner reports them
+    let watched_findings = args.watch.then(|| report.findings.clone());
+    present_findings(
+        &mut report.findings,
+        relative_to.as_deref(),
+        args.posix_paths,
+        args.sort,
+    );
+    present_findings(
+        &mut report.tests,
+        relative_to.as_deref(),
+        args.posix_paths,
+        args.sort,
+    );
+
+    if let Some(path) = &args.write_baseline {
+        if let Err(err) = write_baseline_file(path, &report.findings) {
+            eprintln!("error: failed to write {}: {}", path.display(), err);
+            std::process::exit(EXIT_ERROR);
+        }
+        if !args.quiet {
+            eprintln!(
+                "Wrote {} unmarked test(s) to baseline {}",
+                report.findings.len(),
+                path.display()
+            );
+        }
+        return;
+    }
+
+    baseline::apply(&mut report.findings, &baseline);
+    let unmarked_tests = &report.findings;
 
-            if !has_excluded_marker {
-                test_functions.push(function_name.to_string());
+    if args.verbose
+        && !args.quiet
+        && let Err(err) = output::write_verbose(&mut std::io::stderr(), &report.tests, |test| {
+            scanner.excluded_by(test)
+        })
+    {
+        eprintln!("error: failed to write output: {}", err);
+        std::process::exit(EXIT_ERROR);
+    }
+
+    if args.count {
+        println!("{}", unmarked_tests.len());
+    } else if let Some(path) = &args.output {
+        if let Err(err) = write_report_file(
+            path,
+            unmarked_tests,
+            args.format,
+            args.output_template.as_ref(),
+            args.group_by_file,
+            args.summary,
+        ) {
+            eprintln!("error: failed to write {}: {}", path.display(), err);
+            std::process::exit(EXIT_ERROR);
+        }
+        if !args.quiet {
+            eprintln!(
+                "Wrote {} unmarked test(s) to {}",
+                unmarked_tests.len(),
+                path.display()
+            );
+        }
+    } else if !args.quiet
+        && let Err(err) = print_report(
+            unmarked_tests,
+            args.format,
+            args.output_template.as_ref(),
+            args.group_by_file,
+            args.check,
+            args.summary,
+            args.color,
+        )
+    {
+        eprintln!("error: failed to write output: {}", err);
+        std::process::exit(EXIT_ERROR);
+    }
+
+    if args.stats
+        && !args.quiet
+        && let Err(err) = output::write_stats(&mut std::io::stdout(), &report.stats)
+    {
+        eprintln!("error: failed to write output: {}", err);
+        std::process::exit(EXIT_ERROR);
+    }
+
+    if args.warn_duplicates
+        && !args.quiet
+        && let Err(err) = output::write_duplicates(&mut std::io::stderr(), &report.tests)
+    {
+        eprintln!("error: failed to write output: {}", err);
+        std::process::exit(EXIT_ERROR);
+    }
+
+    if !args.quiet {
+        for skipped in &report.skipped {
+            eprintln!("warning: skipping {}", skipped);
+        }
+        if !report.skipped.is_empty() {
+            eprintln!(
+                "warning: {} file(s) could not be scanned",
+                report.skipped.len()
+            );
+        }
+    }
+
+    if args.time && !args.quiet {
+        print_timing(scanned_files.load(Ordering::Relaxed), start.elapsed());
+    }
+
+    if args.summary_json
+        && let Err(err) = output::write_summary_json(
+            &mut std::io::stdout(),
+            unmarked_tests,
+            scanned_files.load(Ordering::Relaxed),
+        )
+    {
+        eprintln!("error: failed to write output: {}", err);
+        std::process::exit(EXIT_ERROR);
+    }
+
+    if let Some(watched_findings) = watched_findings {
+        let result = watch::watch(&scanner, &directories, watched_findings, |findings| {
+            let mut findings = findings.to_vec();
+            present_findings(
+                &mut findings,
+                relative_to.as_deref(),
+                args.posix_paths,
+                args.sort,
+            );
+            baseline::apply(&mut findings, &baseline);
+            eprintln!();
+            if let Err(err) = print_report(
+                &findings,
+                args.format,
+                args.output_template.as_ref(),
+                args.group_by_file,
+                args.check,
+                args.summary,
+                args.color,
+            ) {
+                eprintln!("error: failed to write output: {}", err);
+                std::process::exit(EXIT_ERROR);
             }
+        });
+        if let Err(err) = result {
+            eprintln!("error: failed to watch for changes: {}", err);
+            std::process::exit(EXIT_ERROR);
         }
+        return;
     }
 
-    test_functions
+    let exit_code = if args.strict && !report.skipped.is_empty() {
+        EXIT_ERROR
+    } else if unmarked_tests.len() <= args.fail_under.unwrap_or(0) || args.exit_zero || args.list {
+        EXIT_CLEAN
+    } else {
+        EXIT_FINDINGS
+    };
+    std::process::exit(exit_code);
 }
 
-fn extract_pytest_marker(decorator_line: &str) -> Option<String> {
-    // Handle various pytest marker formats:
-    // @pytest.mark.unit
-    // @pytest.mark.parametrize(...)
-    // @pytest.mark.skip
+/// Rewrite the paths of `findings` as they are reported, relative to `relative_to` and with
+/// `/` separators if requested, and sort them.
+fn present_findings(
+    findings: &mut [Finding],
+    relative_to: Option<&Path>,
+    posix_paths: bool,
+    sort: SortKey,
+) {
+    if let Some(base) = relative_to {
+        output::use_relative_paths(findings, base);
+    }
+    if posix_paths {
+        output::use_posix_paths(findings);
+    }
+    output::sort_findings(findings, sort);
+}
+
+/// Print the findings, and the summary if requested, to the stream of the given format.
+///
+/// With `ColorChoice::Auto`, colors are only used on a terminal and unless `NO_COLOR` is set.
+fn print_report(
+    findings: &[Finding],
+    format: OutputFormat,
+    template: Option<&OutputTemplate>,
+    group_by_file: bool,
+    check: bool,
+    summary: bool,
+    color: ColorChoice,
+) -> std::io::Result<()> {
+    let color = match color {
+        ColorChoice::Auto => anstream::ColorChoice::Auto,
+        ColorChoice::Always => anstream::ColorChoice::Always,
+        ColorChoice::Never => anstream::ColorChoice::Never,
+    };
+    let mut stderr = AutoStream::new(std::io::stderr(), color);
+
+    if let Some(template) = template {
+        template.write(&mut std::io::stdout(), findings)?;
+        if summary {
+            output::write_summary(&mut stderr, findings)?;
+        }
+        return Ok(());
+    }
 
-    let marker_regex = Regex::new(r"@(?:pytest\.mark\.)?(\w+)").unwrap();
+    // Machine-readable formats write their empty result instead, e.g. a CSV header
+    match format {
+        OutputFormat::Text if findings.is_empty() => println!("No unmarked tests found."),
+        OutputFormat::Text if check => output::write_check(&mut stderr, findings)?,
+        OutputFormat::Text if group_by_file => output::write_grouped(&mut stderr, findings)?,
+        OutputFormat::Text => output::write_findings(&mut stderr, findings, format)?,
+        // Workflow commands are only picked up from stdout
+        OutputFormat::Github | OutputFormat::Junit | OutputFormat::Sarif | OutputFormat::Csv => {
+            output::write_findings(&mut std::io::stdout(), findings, format)?
+        }
+    }
+    if summary {
+        output::write_summary(&mut stderr, findings)?;
+    }
+    Ok(())
+}
 
-    marker_regex
-        .captures(decorator_line)
-        .map(|captures| captures.get(1).unwrap().as_str().to_string())
+/// Write the findings, and the summary if requested, to a file in the given format.
+fn write_report_file(
+    path: &Path,
+    findings: &[Finding],
+    format: OutputFormat,
+    template: Option<&OutputTemplate>,
+    group_by_file: bool,
+    summary: bool,
+) -> std::io::Result<()> {
+    // Files never get colors
+    let mut file = BufWriter::new(StripStream::new(File::create(path)?));
+    if let Some(template) = template {
+        template.write(&mut file, findings)?;
+    } else if group_by_file && format == OutputFormat::Text {
+        output::write_grouped(&mut file, findings)?;
+    } else {
+        output::write_findings(&mut file, findings, format)?;
+    }
+    if summary {
+        output::write_summary(&mut file, findings)?;
+    }
+    file.flush()
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+fn write_baseline_file(path: &Path, findings: &[Finding]) -> std::io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    baseline::write(&mut file, findings)?;
+    file.flush()
+}
 
-    #[test]
-    fn test_extract_pytest_marker() {
-        assert_eq!(
-            extract_pytest_marker("@pytest.mark.unit"),
-            Some("unit".to_string())
-        );
-        assert_eq!(
-            extract_pytest_marker("@pytest.mark.slow"),
-            Some("slow".to_string())
-        );
-        assert_eq!(extract_pytest_marker("@unit"), Some("unit".to_string()));
-        assert_eq!(extract_pytest_marker("@skip"), Some("skip".to_string()));
-        assert_eq!(
-            extract_pytest_marker("@pytest.mark.parametrize('x', [1, 2])"),
-            Some("parametrize".to_string())
-        );
+/// Write `key: value` lines with aligned values; empty values are left out.
+fn write_config(out: &mut impl Write, entries: &[(&str, String)]) -> std::io::Result<()> {
+    let width = entries
+        .iter()
+        .map(|(key, _)| key.len() + 1)
+        .max()
+        .unwrap_or(0);
+    for (key, value) in entries {
+        if !value.is_empty() {
+            writeln!(out, "{:width$} {}", format!("{}:", key), value)?;
+        }
     }
+    Ok(())
+}
 
-    #[test]
-    fn test_find_python_test_functions() {
-        let content = r#"
-import pytest
+/// The name of `value` as given on the command line, e.g. `regex` for `ParserKind::Regex`.
+fn value_name(value: impl ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
 
-@pytest.mark.unit
-def test_marked_function():
-    pass
+fn print_timing(files: usize, elapsed: Duration) {
+    let seconds = elapsed.as_secs_f64();
+    let files_per_second = if seconds > 0.0 {
+        files as f64 / seconds
+    } else {
+        0.0
+    };
+    eprintln!(
+        "Scanned {} file(s) in {:.2}s ({:.0} files/s)",
+        files, seconds, files_per_second
+    );
+}
 
-def test_unmarked_function():
-    pass
+fn progress_spinner() -> ProgressBar {
+    let progress = ProgressBar::new_spinner().with_style(
+        ProgressStyle::with_template("{spinner} Scanned {pos} file(s) ({elapsed})")
+            .expect("progress template is valid"),
+    );
+    progress.enable_steady_tick(Duration::from_millis(100));
+    progress
+}
 
-@pytest.mark.skip
-def test_skipped_function():
-    pass
+/// Accept extensions with or without the leading dot, e.g. `pyx` or `.pyx`.
+fn parse_extension(value: &str) -> Result<String, String> {
+    match value.trim().trim_start_matches('.') {
+        "" => Err("extensions must not be empty".to_string()),
+        extension => Ok(extension.to_string()),
+    }
+}
 
-def test_another_unmarked():
-    pass
-"#;
+/// Accept decorator names with or without the leading `@`, e.g. `mock.patch` or `@mock.patch`.
+fn parse_decorator(value: &str) -> Result<String, String> {
+    match value.trim().trim_start_matches('@') {
+        "" => Err("decorator names must not be empty".to_string()),
+        decorator => Ok(decorator.to_string()),
+    }
+}
 
-        let exclude_markers: HashSet<String> =
-            ["unit", "skip"].iter().map(|s| s.to_string()).collect();
-        let result = find_python_test_functions(content, &exclude_markers);
+/// Trim whitespace around a marker name, e.g. after the comma in `--exclude-markers "unit, slow"`.
+fn parse_marker(value: &str) -> Result<String, String> {
+    match value.trim() {
+        "" => Err("marker names must not be empty".to_string()),
+        marker => Ok(marker.to_string()),
+    }
+}
 
-        assert_eq!(
-            result,
-            vec!["test_unmarked_function", "test_another_unmarked"]
-        );
+/// Expand the glob patterns among `packages`, e.g. `packages/*/tests`, into the directories
+/// they match; other entries are plain paths.
+///
+/// Packages that don't exist and patterns that match no directory are skipped with a
+/// warning.
+fn expand_packages(packages: &[String], quiet: bool) -> Result<Vec<PathBuf>, String> {
+    let mut directories = Vec::new();
+    for package in packages {
+        if !package.contains(['*', '?', '[']) {
+            let package_dir = PathBuf::from(package);
+            if package_dir.exists() {
+                directories.push(package_dir);
+            } else if !quiet {
+                eprintln!(
+                    "warning: skipping package {}: no such directory",
+                    package_dir.display()
+                );
+            }
+            continue;
+        }
+
+        let matches = glob::glob(package)
+            .map_err(|err| format!("invalid package glob {}: {}", package, err))?;
+        let matched: Vec<PathBuf> = matches
+            .filter_map(Result::ok)
+            .filter(|path| path.is_dir())
+            .collect();
+        if matched.is_empty() && !quiet {
+            eprintln!("warning: package glob {} matches no directory", package);
+        }
+        directories.extend(matched);
     }
+    Ok(directories)
+}
 
-    #[test]
-    fn test_multiline_decorator() {
-        let content = r#"
-import pytest
+/// The directories to scan as given, before package globs are expanded.
+#[derive(Debug, PartialEq, Eq)]
+enum ScanRoots {
+    Packages(Vec<String>),
+    Directories(Vec<PathBuf>),
+}
+
+/// Pick the directories to scan and where they come from: the command line (`--packages`
+/// or positional directories), then the environment, then pyproject.toml `packages` and
+/// `test_dir`.
+fn resolve_scan_roots(
+    packages: Option<Vec<String>>,
+    test_dirs: Vec<PathBuf>,
+    env_test_dir: Option<String>,
+    config_packages: Option<Vec<String>>,
+    config_test_dir: Option<PathBuf>,
+) -> (ScanRoots, &'static str) {
+    if let Some(packages) = packages {
+        (ScanRoots::Packages(packages), "--packages")
+    } else if !test_dirs.is_empty() {
+        (ScanRoots::Directories(test_dirs), "command line")
+    } else if let Some(test_dir) = env_test_dir {
+        (
+            ScanRoots::Directories(vec![PathBuf::from(test_dir)]),
+            TEST_DIR_ENV,
+        )
+    } else if let Some(packages) = config_packages {
+        (ScanRoots::Packages(packages), "pyproject.toml packages")
+    } else if let Some(test_dir) = config_test_dir {
+        (
+            ScanRoots::Directories(vec![test_dir]),
+            "pyproject.toml test_dir",
+        )
+    } else {
+        (
+            ScanRoots::Directories(vec![PathBuf::from("tests")]),
+            "built-in default",
+        )
+    }
+}
+
+/// Parse a comma-separated list of markers, as given to `--exclude-markers`.
+fn parse_markers(value: &str) -> Result<Vec<String>, String> {
+    value.split(',').map(parse_marker).collect()
+}
+
+/// The value of the environment variable `name`, unless it is unset or empty.
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+fn parse_marker_alias(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((from, to)) if !from.trim().is_empty() && !to.trim().is_empty() => {
+            Ok((from.trim().to_string(), to.trim().to_string()))
+        }
+        _ => Err("expected FROM=TO, e.g. skipif=skip".to_string()),
+    }
+}
+
+fn build_glob_set(globs: Vec<Glob>) -> GlobSet {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(glob);
+    }
+    builder
+        .build()
+        .expect("globs were validated while parsing arguments")
+}
+
+/// Warn about markers that aren't registered in the nearest pytest configuration, which
+/// are most likely misspelled and so match no test.
+fn check_registered_markers(markers: &HashSet<String>) {
+    let registered = match discover_registered_markers() {
+        Ok(Some(registered)) => registered,
+        Ok(None) => {
+            eprintln!("warning: no pytest configuration found to check markers against");
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(EXIT_ERROR);
+        }
+    };
+
+    let mut unregistered: Vec<&String> = markers
+        .iter()
+        .filter(|marker| {
+            !registered.contains(marker) && !PYTEST_BUILTIN_MARKERS.contains(&marker.as_str())
+        })
+        .collect();
+    unregistered.sort();
+    for marker in unregistered {
+        eprintln!("warning: marker '{}' is not registered with pytest", marker);
+    }
+}
 
-@pytest.mark.unit
-@pytest.mark.parametrize(
-    "arg1, arg2",
-    [
-        pytest.param("a", "b"),
-        pytest.param("c", "d"),
-    ],
-)
-def test_with_multiline_decorator():
-    pass
+/// Markers registered in the nearest pytest configuration, together with pytest's builtin
+/// markers, so that only tests without any known marker are reported.
+fn known_pytest_markers() -> Vec<String> {
+    let registered = match discover_registered_markers() {
+        Ok(Some(registered)) => registered,
+        Ok(None) => {
+            eprintln!("error: no pytest configuration found to read markers from");
+            std::process::exit(EXIT_ERROR);
+        }
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(EXIT_ERROR);
+        }
+    };
 
-def test_unmarked():
-    pass
-"#;
+    registered
+        .into_iter()
+        .chain(
+            PYTEST_BUILTIN_MARKERS
+                .iter()
+                .map(|marker| marker.to_string()),
+        )
+        .collect()
+}
 
-        let exclude_markers: HashSet<String> = ["unit"].iter().map(|s| s.to_string()).collect();
-        let result = find_python_test_functions(content, &exclude_markers);
+/// Read newline-separated paths of Python files, e.g. from `git diff --name-only`; other
+/// files are ignored.
+fn read_files_from_stdin(scanner: &Scanner) -> Vec<PathBuf> {
+    std::io::stdin()
+        .lines()
+        .map_while(Result::ok)
+        .map(|line| PathBuf::from(line.trim()))
+        .filter(|path| scanner.has_scanned_extension(path))
+        .filter(|path| {
+            let exists = path.is_file();
+            if !exists {
+                eprintln!("warning: skipping {}: no such file", path.display());
+            }
+            exists
+        })
+        .collect()
+}
 
-        assert_eq!(result, vec!["test_unmarked"]);
+/// A finding without markers, for the tests of the output formats and baseline files.
+#[cfg(test)]
+fn test_finding(file: &str, line: usize, class_path: &[&str], function: &str) -> Finding {
+    Finding {
+        file: PathBuf::from(file),
+        line,
+        class_path: class_path.iter().map(|name| name.to_string()).collect(),
+        function: function.to_string(),
+        markers: Default::default(),
+        marker_sources: Default::default(),
+        cases: None,
+        marker_count: None,
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
     #[test]
-    fn test_class_methods() {
-        let content = r#"
-import pytest
+    fn test_markers_are_trimmed() {
+        let args =
+            Args::try_parse_from(["collect-unmarked-tests", "--exclude-markers", "unit, slow"])
+                .unwrap();
+        assert_eq!(
+            args.exclude_markers,
+            Some(vec!["unit".to_string(), "slow".to_string()])
+        );
 
-class TestExample:
-    @pytest.mark.unit
-    def test_marked_method(self):
-        pass
+        let args =
+            Args::try_parse_from(["collect-unmarked-tests", "--require-markers", " unit ,e2e"])
+                .unwrap();
+        assert_eq!(
+            args.require_markers,
+            Some(vec!["unit".to_string(), "e2e".to_string()])
+        );
+    }
 
-    def test_unmarked_method(self):
-        pass
+    #[test]
+    fn test_empty_markers_are_rejected() {
+        for markers in ["", "unit,,slow", "unit, "] {
+            let Err(err) =
+                Args::try_parse_from(["collect-unmarked-tests", "--exclude-markers", markers])
+            else {
+                panic!("empty marker accepted in {:?}", markers);
+            };
+            assert!(err.to_string().contains("marker names must not be empty"));
+        }
+    }
 
-    @pytest.mark.integration
-    def test_another_marked_method(self):
-        pass
+    #[test]
+    fn test_parse_markers() {
+        assert_eq!(
+            parse_markers("unit, slow").unwrap(),
+            vec!["unit".to_string(), "slow".to_string()]
+        );
+        assert!(parse_markers("unit,,slow").is_err());
+    }
 
-def test_function_level():
-    pass
+    #[test]
+    fn test_skipif_counts_as_skip() {
+        let parse = |argv: &[&str]| {
+            Args::try_parse_from([&["collect-unmarked-tests"], argv].concat())
+                .unwrap()
+                .skipif_counts_as_skip
+        };
+        assert_eq!(parse(&[]), None);
+        assert_eq!(parse(&["--skipif-counts-as-skip"]), Some(true));
+        assert_eq!(parse(&["--skipif-counts-as-skip=true"]), Some(true));
+        assert_eq!(parse(&["--skipif-counts-as-skip=false"]), Some(false));
+    }
 
-class TestAnother:
-    def test_unmarked_in_class(self):
-        pass
-"#;
+    #[test]
+    fn test_scan_roots_precedence() {
+        let config_packages = || Some(vec!["packages/*/tests".to_string()]);
 
-        let exclude_markers: HashSet<String> = ["unit", "integration"]
-            .iter()
-            .map(|s| s.to_string())
-            .collect();
-        let result = find_python_test_functions(content, &exclude_markers);
+        assert_eq!(
+            resolve_scan_roots(
+                None,
+                vec![PathBuf::from("tests/")],
+                None,
+                config_packages(),
+                None
+            ),
+            (
+                ScanRoots::Directories(vec![PathBuf::from("tests/")]),
+                "command line"
+            )
+        );
+        assert_eq!(
+            resolve_scan_roots(
+                None,
+                Vec::new(),
+                Some("src/tests".to_string()),
+                config_packages(),
+                None
+            ),
+            (
+                ScanRoots::Directories(vec![PathBuf::from("src/tests")]),
+                TEST_DIR_ENV
+            )
+        );
+        assert_eq!(
+            resolve_scan_roots(
+                None,
+                Vec::new(),
+                None,
+                config_packages(),
+                Some(PathBuf::from("tests"))
+            ),
+            (
+                ScanRoots::Packages(vec!["packages/*/tests".to_string()]),
+                "pyproject.toml packages"
+            )
+        );
+        assert_eq!(
+            resolve_scan_roots(
+                Some(vec!["pkg/tests".to_string()]),
+                vec![PathBuf::from("tests/")],
+                None,
+                config_packages(),
+                None
+            ),
+            (
+                ScanRoots::Packages(vec!["pkg/tests".to_string()]),
+                "--packages"
+            )
+        );
+    }
 
+    #[test]
+    fn test_expand_packages() {
+        let dir = tempfile::tempdir().unwrap();
+        for package in ["packages/b/tests", "packages/a/tests", "packages/c/src"] {
+            std::fs::create_dir_all(dir.path().join(package)).unwrap();
+        }
+        std::fs::write(dir.path().join("packages/test_file.py"), "").unwrap();
+        let path = |relative: &str| dir.path().join(relative).display().to_string();
+
+        let packages = [
+            path("packages/*/tests"),
+            path("packages/c/src"),
+            path("packages/missing"),
+            path("packages/*/docs"),
+        ];
         assert_eq!(
-            result,
+            expand_packages(&packages, true).unwrap(),
             vec![
-                "test_unmarked_method",
-                "test_function_level",
-                "test_unmarked_in_class"
+                dir.path().join("packages/a/tests"),
+                dir.path().join("packages/b/tests"),
+                dir.path().join("packages/c/src"),
             ]
         );
+
+        assert!(expand_packages(&[path("packages/[a")], true).is_err());
     }
 
     #[test]
-    fn test_class_level_markers() {
-        let content = r#"
-import pytest
+    fn test_no_markers_only_conflicts_with_marker_selection() {
+        assert!(Args::try_parse_from(["collect-unmarked-tests", "--no-markers-only"]).is_ok());
+        for flags in [
+            ["--no-markers-only", "--exclude-markers=unit"],
+            ["--no-markers-only", "--min-markers=2"],
+            ["--no-markers-only", "--marker-source=function"],
+        ] {
+            let mut argv = vec!["collect-unmarked-tests"];
+            argv.extend(flags);
+            assert!(Args::try_parse_from(argv).is_err(), "{:?} accepted", flags);
+        }
+    }
 
-@pytest.mark.unit
-class TestMarkedClass:
-    def test_method_in_marked_class(self):
-        pass
+    #[test]
+    fn test_write_config() {
+        let entries = [
+            (
+                "mode",
+                "report tests without an excluded marker".to_string(),
+            ),
+            ("marker patterns", String::new()),
+            ("parser", "regex".to_string()),
+        ];
+        let mut out = Vec::new();
+        write_config(&mut out, &entries).unwrap();
 
-    @pytest.mark.integration
-    def test_method_with_own_marker(self):
-        pass
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "mode:            report tests without an excluded marker\nparser:          regex\n"
+        );
+    }
 
-class TestUnmarkedClass:
-    def test_method_in_unmarked_class(self):
-        pass
+    #[test]
+    fn test_check_and_list_are_exclusive() {
+        let args = Args::try_parse_from(["collect-unmarked-tests", "--check"]).unwrap();
+        assert!(args.check && !args.list);
+        let args = Args::try_parse_from(["collect-unmarked-tests", "--list"]).unwrap();
+        assert!(args.list && !args.check);
+
+        for flags in [
+            ["--check", "--list"],
+            ["--check", "--exit-zero"],
+            ["--check", "--group-by-file"],
+            ["--list", "--exit-zero"],
+            ["--list", "--quiet"],
+        ] {
+            let mut argv = vec!["collect-unmarked-tests"];
+            argv.extend(flags);
+            assert!(Args::try_parse_from(argv).is_err(), "{:?} accepted", flags);
+        }
+    }
 
-def test_function_level():
-    pass
-"#;
+    #[test]
+    fn test_summary_json_conflicts_with_quiet() {
+        let args = Args::try_parse_from(["collect-unmarked-tests", "--summary-json"]).unwrap();
+        assert!(args.summary_json);
+        assert!(
+            Args::try_parse_from(["collect-unmarked-tests", "--summary-json", "--quiet"]).is_err()
+        );
+    }
 
-        let exclude_markers: HashSet<String> = ["unit", "integration"]
-            .iter()
-            .map(|s| s.to_string())
-            .collect();
-        let result = find_python_test_functions(content, &exclude_markers);
+    #[test]
+    fn test_fail_fast_conflicts_with_other_exit_policies() {
+        let args = Args::try_parse_from(["collect-unmarked-tests", "--fail-fast"]).unwrap();
+        assert!(args.fail_fast);
+
+        for flag in [
+            "--list",
+            "--exit-zero",
+            "--fail-under=3",
+            "--baseline=.unmarked-baseline",
+            "--stats",
+            "--watch",
+        ] {
+            let argv = ["collect-unmarked-tests", "--fail-fast", flag];
+            assert!(Args::try_parse_from(argv).is_err(), "{} accepted", flag);
+        }
+    }
 
+    #[test]
+    fn test_watch_rescan_with_relative_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let tests = dir.path().join("tests");
+        std::fs::create_dir(&tests).unwrap();
+        std::fs::write(tests.join("test_x.py"), "def test_a():\n    pass\n").unwrap();
+        std::fs::write(tests.join("test_y.py"), "def test_b():\n    pass\n").unwrap();
+
+        let scanner = Scanner::new(HashSet::new()).directories([&tests]);
+        let cwd = std::env::current_dir().unwrap();
+        let mut watched = watch::WatchedFindings::new(scanner.scan().findings, cwd);
+
+        std::fs::write(
+            tests.join("test_x.py"),
+            "def test_a():\n    pass\n\ndef test_c():\n    pass\n",
+        )
+        .unwrap();
+        let mut findings =
+            watched.rescan(&scanner, [tests.join("test_x.py")].into_iter().collect());
+        present_findings(&mut findings, Some(&tests), false, SortKey::Path);
+
+        let reported: Vec<String> = findings.iter().map(ToString::to_string).collect();
         assert_eq!(
-            result,
-            vec!["test_method_in_unmarked_class", "test_function_level"]
+            reported,
+            vec![
+                "test_x.py:1::test_a",
+                "test_x.py:4::test_c",
+                "test_y.py:1::test_b"
+            ]
         );
     }
 }
@@ -0,0 +1,792 @@
+//! Rendering findings in the supported output formats.
+
+mod sarif;
+mod template;
+
+use anstyle::{AnsiColor, Style};
+use clap::ValueEnum;
+use collect_unmarked_tests::{Finding, MarkerStats};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+pub use template::OutputTemplate;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable list
+    #[default]
+    Text,
+    /// GitHub Actions workflow annotations
+    Github,
+    /// JUnit XML report with a failed test case per finding (requires --output)
+    Junit,
+    /// SARIF 2.1.0 log for code scanning dashboards
+    Sarif,
+    /// CSV rows of `file,line,class,function,markers` with a header, for spreadsheets
+    Csv,
+}
+
+/// Order in which findings are reported.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum SortKey {
+    /// By file, then line
+    #[default]
+    Path,
+    /// By test name, including enclosing classes, then file and line
+    Name,
+    /// By line, then file
+    Line,
+    /// In the order the directories were scanned
+    None,
+}
+
+const HEADER: Style = AnsiColor::Red.on_default().bold();
+const LOCATION: Style = AnsiColor::Cyan.on_default();
+
+/// Report the paths of findings relative to the absolute directory `base`, keeping the
+/// absolute path of findings outside of it.
+pub fn use_relative_paths(findings: &mut [Finding], base: &Path) {
+    for finding in findings {
+        if let Ok(path) = std::path::absolute(&finding.file) {
+            finding.file = relative_path(&path, base);
+        }
+    }
+}
+
+fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    match path.strip_prefix(base) {
+        Ok(relative) if relative.as_os_str().is_empty() => PathBuf::from("."),
+        Ok(relative) => relative.to_path_buf(),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+/// Sort findings by `key`.
+pub fn sort_findings(findings: &mut [Finding], key: SortKey) {
+    match key {
+        SortKey::Path => findings.sort_by(|a, b| (&a.file, a.line).cmp(&(&b.file, b.line))),
+        SortKey::Name => findings.sort_by_cached_key(|finding| {
+            (finding.qualified_name(), finding.file.clone(), finding.line)
+        }),
+        SortKey::Line => findings.sort_by(|a, b| (a.line, &a.file).cmp(&(b.line, &b.file))),
+        SortKey::None => {}
+    }
+}
+
+/// Use `/` as the separator in the reported paths of findings, on any platform.
+pub fn use_posix_paths(findings: &mut [Finding]) {
+    for finding in findings {
+        finding.file = PathBuf::from(posix_path(
+            &finding.file.to_string_lossy(),
+            std::path::MAIN_SEPARATOR,
+        ));
+    }
+}
+
+fn posix_path(path: &str, separator: char) -> String {
+    if separator == '/' {
+        path.to_string()
+    } else {
+        path.replace(separator, "/")
+    }
+}
+
+/// Write the findings in the given format.
+///
+/// Text output is styled with ANSI colors; wrap `out` in an `anstream` stream to strip
+/// them where colors aren't wanted.
+pub fn write_findings(
+    out: &mut impl Write,
+    findings: &[Finding],
+    format: OutputFormat,
+) -> io::Result<()> {
+    match format {
+        OutputFormat::Text => write_text(out, findings),
+        OutputFormat::Github => write_github(out, findings),
+        OutputFormat::Junit => write_junit(out, findings),
+        OutputFormat::Sarif => sarif::write_sarif(out, findings),
+        OutputFormat::Csv => write_csv(out, findings),
+    }
+}
+
+fn write_text(out: &mut impl Write, findings: &[Finding]) -> io::Result<()> {
+    writeln!(
+        out,
+        "{HEADER}Found {} unmarked test(s):{HEADER:#}",
+        findings.len()
+    )?;
+    for finding in findings {
+        writeln!(
+            out,
+            "  {LOCATION}{}:{}{LOCATION:#}::{}{}{}",
+            finding.file.display(),
+            finding.line,
+            finding.qualified_name(),
+            cases_suffix(finding),
+            marker_count_suffix(finding)
+        )?;
+    }
+    Ok(())
+}
+
+/// Write only the number of findings as text, without listing them (`--check`).
+pub fn write_check(out: &mut impl Write, findings: &[Finding]) -> io::Result<()> {
+    writeln!(
+        out,
+        "{HEADER}Found {} unmarked test(s).{HEADER:#}",
+        findings.len()
+    )
+}
+
+/// ` (xN)` for a test that a `parametrize` decorator expands into N cases.
+fn cases_suffix(finding: &Finding) -> String {
+    match finding.cases {
+        Some(cases) => format!(" (x{})", cases),
+        None => String::new(),
+    }
+}
+
+/// ` (N marker(s))` for a test reported for carrying too few markers.
+fn marker_count_suffix(finding: &Finding) -> String {
+    match finding.marker_count {
+        Some(count) => format!(" ({} marker(s))", count),
+        None => String::new(),
+    }
+}
+
+/// Write the findings as text, listing each file once with its tests indented beneath it.
+///
+/// Files are sorted by path and the tests within each file by line.
+pub fn write_grouped(out: &mut impl Write, findings: &[Finding]) -> io::Result<()> {
+    let mut by_file: BTreeMap<&Path, Vec<&Finding>> = BTreeMap::new();
+    for finding in findings {
+        by_file.entry(&finding.file).or_default().push(finding);
+    }
+
+    writeln!(
+        out,
+        "{HEADER}Found {} unmarked test(s):{HEADER:#}",
+        findings.len()
+    )?;
+    for (file, mut tests) in by_file {
+        tests.sort_by_key(|test| test.line);
+        writeln!(out, "  {LOCATION}{}{LOCATION:#}", file.display())?;
+        for test in tests {
+            writeln!(
+                out,
+                "    {}::{}{}{}",
+                test.line,
+                test.qualified_name(),
+                cases_suffix(test),
+                marker_count_suffix(test)
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn write_github(out: &mut impl Write, findings: &[Finding]) -> io::Result<()> {
+    for finding in findings {
+        writeln!(
+            out,
+            "::warning file={},line={}::{}",
+            escape_github_property(&finding.file.display().to_string()),
+            finding.line,
+            escape_github_data(&format!(
+                "Test '{}' is missing a marker",
+                finding.qualified_name()
+            )),
+        )?;
+    }
+    Ok(())
+}
+
+/// Write the number of findings per file, most affected files first, and a grand total.
+pub fn write_summary(out: &mut impl Write, findings: &[Finding]) -> io::Result<()> {
+    let mut counts: HashMap<&Path, usize> = HashMap::new();
+    for finding in findings {
+        *counts.entry(finding.file.as_path()).or_default() += 1;
+    }
+
+    let mut counts: Vec<(&Path, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+    let width = findings.len().to_string().len();
+    writeln!(out, "Unmarked tests per file:")?;
+    for (file, count) in counts {
+        writeln!(out, "  {:>width$}  {}", count, file.display())?;
+    }
+    writeln!(out, "  {:>width$}  total", findings.len())
+}
+
+/// Write, for every test, the markers found at each level and whether it is reported.
+pub fn write_verbose(
+    out: &mut impl Write,
+    tests: &[Finding],
+    excluded_by: impl Fn(&Finding) -> Vec<&str>,
+) -> io::Result<()> {
+    for test in tests {
+        let excluded_by = excluded_by(test);
+        let decision = if excluded_by.is_empty() {
+            "unmarked".to_string()
+        } else {
+            format!("excluded by {}", excluded_by.join(", "))
+        };
+
+        writeln!(
+            out,
+            "{}: module [{}], class [{}], function [{}] -> {}",
+            test,
+            join_markers(&test.marker_sources.module),
+            join_markers(&test.marker_sources.class),
+            join_markers(&test.marker_sources.function),
+            decision
+        )?;
+    }
+    Ok(())
+}
+
+fn join_markers(markers: &BTreeSet<String>) -> String {
+    markers
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Write how many tests carry each marker, most common first; `<none>` counts tests
+/// without any marker.
+pub fn write_stats(out: &mut impl Write, stats: &MarkerStats) -> io::Result<()> {
+    let mut counts: Vec<(&str, usize)> = stats
+        .markers
+        .iter()
+        .map(|(marker, count)| (marker.as_str(), *count))
+        .collect();
+    counts.push(("<none>", stats.without_markers));
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+    for (marker, count) in counts {
+        writeln!(out, "{}: {}", marker, count)?;
+    }
+    Ok(())
+}
+
+/// Write a single JSON line summarizing the run, e.g.
+/// `{"found":14,"scanned_files":1203}`, for collecting metrics.
+pub fn write_summary_json(
+    out: &mut impl Write,
+    findings: &[Finding],
+    scanned_files: usize,
+) -> io::Result<()> {
+    let summary = serde_json::json!({
+        "found": findings.len(),
+        "scanned_files": scanned_files,
+    });
+    writeln!(out, "{}", summary)
+}
+
+/// Warn about tests defined more than once in the same scope of a file, where the later
+/// definition silently shadows the earlier one.
+pub fn write_duplicates(out: &mut impl Write, tests: &[Finding]) -> io::Result<()> {
+    let mut definitions: BTreeMap<(&Path, String), Vec<usize>> = BTreeMap::new();
+    for test in tests {
+        definitions
+            .entry((&test.file, test.qualified_name()))
+            .or_default()
+            .push(test.line);
+    }
+
+    for ((file, name), mut lines) in definitions {
+        if lines.len() < 2 {
+            continue;
+        }
+        lines.sort_unstable();
+        let lines: Vec<String> = lines.iter().map(usize::to_string).collect();
+        writeln!(
+            out,
+            "warning: {}::{} is defined {} times, on lines {}; only the last one runs",
+            file.display(),
+            name,
+            lines.len(),
+            lines.join(", ")
+        )?;
+    }
+    Ok(())
+}
+
+/// Write one row per finding; nested classes are joined with `::` and markers with `,`.
+fn write_csv(out: &mut impl Write, findings: &[Finding]) -> io::Result<()> {
+    let mut writer = csv::Writer::from_writer(out);
+    writer.write_record(["file", "line", "class", "function", "markers"])?;
+    for finding in findings {
+        let markers: Vec<&str> = finding.markers.iter().map(String::as_str).collect();
+        writer.write_record([
+            finding.file.display().to_string(),
+            finding.line.to_string(),
+            finding.class_path.join("::"),
+            finding.function.clone(),
+            markers.join(","),
+        ])?;
+    }
+    writer.flush()
+}
+
+fn write_junit(out: &mut impl Write, findings: &[Finding]) -> io::Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<testsuite name="collect-unmarked-tests" tests="{0}" failures="{0}">"#,
+        findings.len()
+    )?;
+    for finding in findings {
+        let file = escape_xml(&finding.file.display().to_string());
+        writeln!(
+            out,
+            r#"  <testcase classname="{}" name="{}" file="{}" line="{}">"#,
+            file,
+            escape_xml(&finding.qualified_name()),
+            file,
+            finding.line
+        )?;
+        writeln!(
+            out,
+            r#"    <failure message="missing marker">{}</failure>"#,
+            escape_xml(&format!("Test '{}' is missing a marker", finding))
+        )?;
+        writeln!(out, "  </testcase>")?;
+    }
+    writeln!(out, "</testsuite>")
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// See https://github.com/actions/toolkit/blob/main/packages/core/src/command.ts
+fn escape_github_data(value: &str) -> String {
+    value
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn escape_github_property(value: &str) -> String {
+    escape_github_data(value)
+        .replace(':', "%3A")
+        .replace(',', "%2C")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_finding as finding;
+
+    fn render(findings: &[Finding], format: OutputFormat) -> String {
+        let mut out = Vec::new();
+        write_findings(&mut anstream::StripStream::new(&mut out), findings, format).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_text_format() {
+        let findings = [finding("tests/test_x.py", 42, &[], "test_foo")];
+
+        assert_eq!(
+            render(&findings, OutputFormat::Text),
+            "Found 1 unmarked test(s):\n  tests/test_x.py:42::test_foo\n"
+        );
+    }
+
+    #[test]
+    fn test_text_format_class_methods() {
+        let method = finding(
+            "tests/test_x.py",
+            7,
+            &["TestFoo", "TestNested"],
+            "test_method",
+        );
+
+        assert_eq!(
+            render(&[method], OutputFormat::Text),
+            "Found 1 unmarked test(s):\n  tests/test_x.py:7::TestFoo::TestNested::test_method\n"
+        );
+    }
+
+    #[test]
+    fn test_text_format_unicode_names() {
+        let method = finding("tests/test_ü.py", 3, &["TestÜber"], "test_日本語");
+
+        assert_eq!(
+            render(&[method], OutputFormat::Text),
+            "Found 1 unmarked test(s):\n  tests/test_ü.py:3::TestÜber::test_日本語\n"
+        );
+    }
+
+    #[test]
+    fn test_text_format_parametrize_cases() {
+        let mut parametrized = finding("tests/test_x.py", 42, &[], "test_foo");
+        parametrized.cases = Some(3);
+
+        assert_eq!(
+            render(&[parametrized], OutputFormat::Text),
+            "Found 1 unmarked test(s):\n  tests/test_x.py:42::test_foo (x3)\n"
+        );
+    }
+
+    #[test]
+    fn test_text_format_marker_count() {
+        let mut finding = finding("tests/test_x.py", 42, &[], "test_foo");
+        finding.cases = Some(2);
+        finding.marker_count = Some(1);
+
+        assert_eq!(
+            render(&[finding], OutputFormat::Text),
+            "Found 1 unmarked test(s):\n  tests/test_x.py:42::test_foo (x2) (1 marker(s))\n"
+        );
+    }
+
+    #[test]
+    fn test_check_omits_listing() {
+        let findings = [
+            finding("tests/test_x.py", 42, &[], "test_foo"),
+            finding("tests/test_y.py", 7, &[], "test_bar"),
+        ];
+        let mut out = Vec::new();
+        write_check(&mut anstream::StripStream::new(&mut out), &findings).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Found 2 unmarked test(s).\n"
+        );
+        assert!(render(&findings, OutputFormat::Text).contains("tests/test_y.py:7::test_bar"));
+    }
+
+    #[test]
+    fn test_csv_format() {
+        let mut method = finding(
+            "tests/test, x.py",
+            7,
+            &["TestFoo", "TestNested"],
+            "test_method",
+        );
+        method.markers = BTreeSet::from(["e2e".to_string(), "flaky".to_string()]);
+        let findings = [finding("tests/test_x.py", 42, &[], "test_foo"), method];
+
+        assert_eq!(
+            render(&findings, OutputFormat::Csv),
+            "file,line,class,function,markers\n\
+             tests/test_x.py,42,,test_foo,\n\
+             \"tests/test, x.py\",7,TestFoo::TestNested,test_method,\"e2e,flaky\"\n"
+        );
+    }
+
+    #[test]
+    fn test_grouped_text() {
+        let method = finding("tests/test_b.py", 7, &["TestFoo"], "test_method");
+        let mut parametrized = finding("tests/test_b.py", 12, &[], "test_late");
+        parametrized.cases = Some(2);
+        let findings = [
+            parametrized,
+            finding("tests/test_a.py", 3, &[], "test_a"),
+            method,
+        ];
+
+        let mut out = Vec::new();
+        write_grouped(&mut anstream::StripStream::new(&mut out), &findings).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Found 3 unmarked test(s):\n\
+             \x20 tests/test_a.py\n\
+             \x20   3::test_a\n\
+             \x20 tests/test_b.py\n\
+             \x20   7::TestFoo::test_method\n\
+             \x20   12::test_late (x2)\n"
+        );
+    }
+
+    #[test]
+    fn test_text_format_colors() {
+        let findings = [finding("tests/test_x.py", 42, &[], "test_foo")];
+
+        let mut out = Vec::new();
+        write_findings(&mut out, &findings, OutputFormat::Text).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\x1b[1m\x1b[31mFound 1 unmarked test(s):\x1b[0m\n  \
+             \x1b[36mtests/test_x.py:42\x1b[0m::test_foo\n"
+        );
+    }
+
+    #[test]
+    fn test_summary() {
+        let findings = [
+            finding("tests/test_b.py", 1, &[], "test_b1"),
+            finding("tests/test_a.py", 1, &[], "test_a1"),
+            finding("tests/test_c.py", 1, &[], "test_c1"),
+            finding("tests/test_c.py", 5, &[], "test_c2"),
+            finding("tests/test_c.py", 9, &[], "test_c3"),
+            finding("tests/test_c.py", 13, &[], "test_c4"),
+            finding("tests/test_c.py", 17, &[], "test_c5"),
+            finding("tests/test_c.py", 21, &[], "test_c6"),
+            finding("tests/test_c.py", 25, &[], "test_c7"),
+            finding("tests/test_c.py", 29, &[], "test_c8"),
+        ];
+
+        let mut out = Vec::new();
+        write_summary(&mut out, &findings).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            concat!(
+                "Unmarked tests per file:\n",
+                "   8  tests/test_c.py\n",
+                "   1  tests/test_a.py\n",
+                "   1  tests/test_b.py\n",
+                "  10  total\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_empty_results() {
+        assert_eq!(render(&[], OutputFormat::Github), "");
+        assert_eq!(
+            render(&[], OutputFormat::Junit),
+            concat!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+                "<testsuite name=\"collect-unmarked-tests\" tests=\"0\" failures=\"0\">\n",
+                "</testsuite>\n",
+            )
+        );
+        assert_eq!(
+            render(&[], OutputFormat::Csv),
+            "file,line,class,function,markers\n"
+        );
+        let sarif: serde_json::Value =
+            serde_json::from_str(&render(&[], OutputFormat::Sarif)).unwrap();
+        assert_eq!(sarif["runs"][0]["results"], serde_json::json!([]));
+
+        let mut out = Vec::new();
+        write_summary(&mut out, &[]).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Unmarked tests per file:\n  0  total\n"
+        );
+
+        let mut out = Vec::new();
+        write_summary_json(&mut out, &[], 0).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"found\":0,\"scanned_files\":0}\n"
+        );
+    }
+
+    #[test]
+    fn test_junit_format() {
+        let method = finding("tests/a&b.py", 7, &["TestFoo"], "test_method");
+        let findings = [finding("tests/test_x.py", 42, &[], "test_foo"), method];
+
+        assert_eq!(
+            render(&findings, OutputFormat::Junit),
+            concat!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+                "<testsuite name=\"collect-unmarked-tests\" tests=\"2\" failures=\"2\">\n",
+                "  <testcase classname=\"tests/test_x.py\" name=\"test_foo\" file=\"tests/test_x.py\" line=\"42\">\n",
+                "    <failure message=\"missing marker\">Test &apos;tests/test_x.py:42::test_foo&apos; is missing a marker</failure>\n",
+                "  </testcase>\n",
+                "  <testcase classname=\"tests/a&amp;b.py\" name=\"TestFoo::test_method\" file=\"tests/a&amp;b.py\" line=\"7\">\n",
+                "    <failure message=\"missing marker\">Test &apos;tests/a&amp;b.py:7::TestFoo::test_method&apos; is missing a marker</failure>\n",
+                "  </testcase>\n",
+                "</testsuite>\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_sort_findings() {
+        let method = finding("tests/a.py", 9, &["TestA"], "test_b");
+        let scanned = vec![
+            finding("tests/b.py", 1, &[], "test_c"),
+            finding("tests/a.py", 5, &[], "test_z"),
+            method,
+            finding("tests/a.py", 2, &[], "test_b"),
+        ];
+        let sorted = |key| {
+            let mut findings = scanned.clone();
+            sort_findings(&mut findings, key);
+            findings
+                .iter()
+                .map(|finding| finding.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            sorted(SortKey::Path),
+            vec![
+                "tests/a.py:2::test_b",
+                "tests/a.py:5::test_z",
+                "tests/a.py:9::TestA::test_b",
+                "tests/b.py:1::test_c",
+            ]
+        );
+        assert_eq!(
+            sorted(SortKey::Name),
+            vec![
+                "tests/a.py:9::TestA::test_b",
+                "tests/a.py:2::test_b",
+                "tests/b.py:1::test_c",
+                "tests/a.py:5::test_z",
+            ]
+        );
+        assert_eq!(
+            sorted(SortKey::Line),
+            vec![
+                "tests/b.py:1::test_c",
+                "tests/a.py:2::test_b",
+                "tests/a.py:5::test_z",
+                "tests/a.py:9::TestA::test_b",
+            ]
+        );
+        assert_eq!(
+            sorted(SortKey::None),
+            vec![
+                "tests/b.py:1::test_c",
+                "tests/a.py:5::test_z",
+                "tests/a.py:9::TestA::test_b",
+                "tests/a.py:2::test_b",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_relative_path() {
+        let base = Path::new("/repo");
+
+        assert_eq!(
+            relative_path(Path::new("/repo/tests/test_x.py"), base),
+            Path::new("tests/test_x.py")
+        );
+        assert_eq!(
+            relative_path(Path::new("/elsewhere/test_x.py"), base),
+            Path::new("/elsewhere/test_x.py")
+        );
+        assert_eq!(
+            relative_path(Path::new("/repository/test_x.py"), base),
+            Path::new("/repository/test_x.py")
+        );
+    }
+
+    #[test]
+    fn test_posix_path() {
+        assert_eq!(
+            posix_path(r"tests\unit\test_x.py", '\\'),
+            "tests/unit/test_x.py"
+        );
+        assert_eq!(posix_path(r"tests/odd\name.py", '/'), r"tests/odd\name.py");
+    }
+
+    #[test]
+    fn test_verbose() {
+        let mut marked = finding("tests/test_x.py", 3, &[], "test_marked");
+        marked.marker_sources.class = BTreeSet::from(["unit".to_string()]);
+        marked.marker_sources.function = BTreeSet::from(["e2e".to_string()]);
+        marked.markers = BTreeSet::from(["e2e".to_string(), "unit".to_string()]);
+        let tests = [marked, finding("tests/test_x.py", 9, &[], "test_plain")];
+
+        let mut out = Vec::new();
+        write_verbose(&mut out, &tests, |test| {
+            test.markers
+                .iter()
+                .map(String::as_str)
+                .filter(|marker| *marker == "unit")
+                .collect()
+        })
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "tests/test_x.py:3::test_marked: module [], class [unit], function [e2e] \
+             -> excluded by unit\n\
+             tests/test_x.py:9::test_plain: module [], class [], function [] -> unmarked\n"
+        );
+    }
+
+    #[test]
+    fn test_stats() {
+        let stats = MarkerStats {
+            tests: 6,
+            without_markers: 2,
+            markers: [("unit", 3), ("e2e", 1), ("slow", 1)]
+                .into_iter()
+                .map(|(marker, count)| (marker.to_string(), count))
+                .collect(),
+        };
+
+        let mut out = Vec::new();
+        write_stats(&mut out, &stats).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "unit: 3\n<none>: 2\ne2e: 1\nslow: 1\n"
+        );
+    }
+
+    #[test]
+    fn test_summary_json() {
+        let findings = [
+            finding("tests/test_x.py", 1, &[], "test_a"),
+            finding("tests/test_x.py", 5, &[], "test_b"),
+        ];
+
+        let mut out = Vec::new();
+        write_summary_json(&mut out, &findings, 3).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"found\":2,\"scanned_files\":3}\n"
+        );
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let method = finding("tests/test_x.py", 8, &["TestFoo"], "test_a");
+        let tests = [
+            finding("tests/test_x.py", 5, &[], "test_a"),
+            finding("tests/test_x.py", 1, &[], "test_a"),
+            method,
+            finding("tests/test_x.py", 3, &[], "test_b"),
+            finding("tests/test_y.py", 1, &[], "test_b"),
+        ];
+
+        let mut out = Vec::new();
+        write_duplicates(&mut out, &tests).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "warning: tests/test_x.py::test_a is defined 2 times, on lines 1, 5; only the last \
+             one runs\n"
+        );
+    }
+
+    #[test]
+    fn test_github_format() {
+        let findings = [
+            finding("tests/test_x.py", 42, &[], "test_foo"),
+            finding("tests/a,b.py", 1, &[], "test_bar"),
+        ];
+
+        assert_eq!(
+            render(&findings, OutputFormat::Github),
+            "::warning file=tests/test_x.py,line=42::Test 'test_foo' is missing a marker\n\
+             ::warning file=tests/a%2Cb.py,line=1::Test 'test_bar' is missing a marker\n"
+        );
+    }
+}
@@ -0,0 +1,344 @@
+//! SARIF 2.1.0 reports, as consumed by GitHub code scanning and many IDEs.
+//!
+//! See <https://docs.oasis-open.org/sarif/sarif/v2.1.0/sarif-v2.1.0.html>.
+
+use collect_unmarked_tests::Finding;
+use serde::Serialize;
+use std::io::{self, Write};
+use std::path::{MAIN_SEPARATOR, Path};
+
+const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+const RULE_ID: &str = "unmarked-test";
+
+#[derive(Serialize)]
+struct Log {
+    #[serde(rename = "$schema")]
+    schema: &'static str,
+    version: &'static str,
+    runs: Vec<Run>,
+}
+
+#[derive(Serialize)]
+struct Run {
+    tool: Tool,
+    results: Vec<SarifResult>,
+}
+
+#[derive(Serialize)]
+struct Tool {
+    driver: Driver,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Driver {
+    name: &'static str,
+    information_uri: &'static str,
+    rules: Vec<Rule>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Rule {
+    id: &'static str,
+    short_description: Message,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SarifResult {
+    rule_id: &'static str,
+    level: &'static str,
+    message: Message,
+    locations: Vec<Location>,
+}
+
+#[derive(Serialize)]
+struct Message {
+    text: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Location {
+    physical_location: PhysicalLocation,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PhysicalLocation {
+    artifact_location: ArtifactLocation,
+    region: Region,
+}
+
+#[derive(Serialize)]
+struct ArtifactLocation {
+    uri: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Region {
+    start_line: usize,
+}
+
+/// Write a SARIF log with one result per finding.
+pub fn write_sarif(out: &mut impl Write, findings: &[Finding]) -> io::Result<()> {
+    let log = Log {
+        schema: SCHEMA,
+        version: "2.1.0",
+        runs: vec![Run {
+            tool: Tool {
+                driver: Driver {
+                    name: env!("CARGO_PKG_NAME"),
+                    information_uri: env!("CARGO_PKG_REPOSITORY"),
+                    rules: vec![Rule {
+                        id: RULE_ID,
+                        short_description: Message {
+                            text: "Test is missing a marker".to_string(),
+                        },
+                    }],
+                },
+            },
+            results: findings.iter().map(result).collect(),
+        }],
+    };
+
+    serde_json::to_writer_pretty(&mut *out, &log)?;
+    writeln!(out)
+}
+
+fn result(finding: &Finding) -> SarifResult {
+    SarifResult {
+        rule_id: RULE_ID,
+        level: "warning",
+        message: Message {
+            text: format!("Test '{}' is missing a marker", finding.qualified_name()),
+        },
+        locations: vec![Location {
+            physical_location: PhysicalLocation {
+                artifact_location: ArtifactLocation {
+                    uri: artifact_uri(&finding.file),
+                },
+                region: Region {
+                    start_line: finding.line,
+                },
+            },
+        }],
+    }
+}
+
+/// The URI of the file at `path`: a relative reference with `/` separators, resolving
+/// against the checkout, or a `file://` URI for an absolute path.
+fn artifact_uri(path: &Path) -> String {
+    let path = super::posix_path(&path.to_string_lossy(), MAIN_SEPARATOR);
+    if path.starts_with('/') {
+        format!("file://{}", percent_encode(&path, ":"))
+    } else if Path::new(&path).is_absolute() {
+        // A Windows path like `C:/tests`
+        format!("file:///{}", percent_encode(&path, ":"))
+    } else {
+        // An unencoded `:` would make the first segment read as a URI scheme
+        percent_encode(&path, "")
+    }
+}
+
+/// Percent-encode every byte of `text` except unreserved URI characters, `/` and those in
+/// `allowed`.
+fn percent_encode(text: &str, allowed: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric()
+            || b"-._~/".contains(&byte)
+            || allowed.as_bytes().contains(&byte)
+        {
+            encoded.push(char::from(byte));
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_finding as finding;
+    use serde_json::Value;
+
+    fn render(findings: &[Finding]) -> String {
+        let mut out = Vec::new();
+        write_sarif(&mut out, findings).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_matches_expected_fixture() {
+        let findings = [
+            finding("tests/test_x.py", 42, &[], "test_foo"),
+            finding("tests/test_y.py", 7, &["TestBar"], "test_method"),
+        ];
+
+        assert_eq!(
+            render(&findings),
+            include_str!("testdata/expected.sarif.json")
+        );
+    }
+
+    /// Fail unless `object` has all of `required` and no properties besides `allowed`, which
+    /// lists those the SARIF 2.1.0 schema defines for it (it disallows any others) that may
+    /// be emitted.
+    fn assert_properties(object: &Value, allowed: &[&str], required: &[&str]) {
+        let object = object.as_object().unwrap();
+        for key in object.keys() {
+            assert!(allowed.contains(&key.as_str()), "unknown property {}", key);
+        }
+        for key in required {
+            assert!(object.contains_key(*key), "missing property {}", key);
+        }
+    }
+
+    /// Check the emitted log against the constraints of the SARIF 2.1.0 schema.
+    #[test]
+    fn test_conforms_to_schema() {
+        let absolute = std::env::current_dir()
+            .unwrap()
+            .join("tests")
+            .join("test z.py");
+        let log: Value = serde_json::from_str(&render(&[
+            finding("tests/test_x.py", 1, &[], "test_a"),
+            finding("tests/test_y.py", 7, &["TestBar"], "test_b"),
+            finding("tests/test #1 100%.py", 3, &[], "test_c"),
+            finding(absolute.to_str().unwrap(), 5, &[], "test_d"),
+        ]))
+        .unwrap();
+
+        assert_properties(
+            &log,
+            &["$schema", "version", "runs", "properties"],
+            &["version", "runs"],
+        );
+        assert_eq!(log["$schema"], SCHEMA);
+        assert_eq!(log["version"], "2.1.0");
+
+        let runs = log["runs"].as_array().unwrap();
+        assert_eq!(runs.len(), 1);
+        let run = &runs[0];
+        assert_properties(run, &["tool", "results", "properties"], &["tool"]);
+        assert_properties(
+            &run["tool"],
+            &["driver", "extensions", "properties"],
+            &["driver"],
+        );
+
+        let driver = &run["tool"]["driver"];
+        assert_properties(
+            driver,
+            &["name", "version", "informationUri", "rules", "properties"],
+            &["name"],
+        );
+        assert!(!driver["name"].as_str().unwrap().is_empty());
+        assert!(
+            driver["informationUri"]
+                .as_str()
+                .unwrap()
+                .starts_with("https://")
+        );
+
+        let rules = driver["rules"].as_array().unwrap();
+        let rule_ids: Vec<&str> = rules
+            .iter()
+            .map(|rule| rule["id"].as_str().unwrap())
+            .collect();
+        for rule in rules {
+            assert_properties(
+                rule,
+                &[
+                    "id",
+                    "name",
+                    "shortDescription",
+                    "fullDescription",
+                    "helpUri",
+                    "properties",
+                ],
+                &["id"],
+            );
+            assert_properties(&rule["shortDescription"], &["text", "markdown"], &["text"]);
+        }
+        // Rules are `uniqueItems`
+        let unique_ids: std::collections::HashSet<&str> = rule_ids.iter().copied().collect();
+        assert_eq!(unique_ids.len(), rule_ids.len());
+
+        let results = run["results"].as_array().unwrap();
+        assert_eq!(results.len(), 4);
+        for result in results {
+            assert_properties(
+                result,
+                &[
+                    "ruleId",
+                    "ruleIndex",
+                    "level",
+                    "message",
+                    "locations",
+                    "properties",
+                ],
+                &["message"],
+            );
+            assert!(rule_ids.contains(&result["ruleId"].as_str().unwrap()));
+            assert!(
+                ["none", "note", "warning", "error"].contains(&result["level"].as_str().unwrap())
+            );
+            assert_properties(&result["message"], &["text", "markdown", "id"], &["text"]);
+
+            for location in result["locations"].as_array().unwrap() {
+                assert_properties(location, &["physicalLocation", "message"], &[]);
+                let physical = &location["physicalLocation"];
+                assert_properties(physical, &["artifactLocation", "region"], &[]);
+                assert_properties(&physical["artifactLocation"], &["uri", "uriBaseId"], &[]);
+                // A relative `uri-reference` with `/` separators or a `file://` URI,
+                // percent-encoded
+                let uri = physical["artifactLocation"]["uri"].as_str().unwrap();
+                assert!(!uri.starts_with('/'), "{}", uri);
+                assert!(
+                    uri.chars()
+                        .all(|ch| ch.is_ascii_alphanumeric() || "-._~/:%".contains(ch)),
+                    "{}",
+                    uri
+                );
+                assert!(
+                    uri.split('%').skip(1).all(|escape| {
+                        escape.len() >= 2
+                            && escape.as_bytes()[..2].iter().all(u8::is_ascii_hexdigit)
+                    }),
+                    "{}",
+                    uri
+                );
+                assert_properties(&physical["region"], &["startLine", "startColumn"], &[]);
+                assert!(physical["region"]["startLine"].as_u64().unwrap() >= 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_artifact_uri() {
+        assert_eq!(
+            artifact_uri(Path::new("tests/test #1 100%.py")),
+            "tests/test%20%231%20100%25.py"
+        );
+        assert_eq!(
+            artifact_uri(Path::new("tests/tëst.py")),
+            "tests/t%C3%ABst.py"
+        );
+        let absolute = std::env::current_dir().unwrap().join("test z.py");
+        let uri = artifact_uri(&absolute);
+        assert!(uri.starts_with("file:///"), "{}", uri);
+        assert!(uri.ends_with("/test%20z.py"), "{}", uri);
+    }
+
+    #[test]
+    fn test_no_findings() {
+        let log: Value = serde_json::from_str(&render(&[])).unwrap();
+
+        assert_eq!(log["runs"][0]["results"], Value::Array(Vec::new()));
+    }
+}
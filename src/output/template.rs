@@ -0,0 +1,147 @@
+//! Findings rendered through a user-given line template (`--output-template`).
+
+use collect_unmarked_tests::Finding;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+const PLACEHOLDERS: &str = "{file}, {line}, {class}, {func}, {markers}";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    File,
+    Line,
+    /// Enclosing classes joined with `::`
+    Class,
+    Func,
+    /// Markers joined with `,`
+    Markers,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Field(Field),
+}
+
+/// A line template such as `{file}:{line}: {func}`, rendered once per finding.
+///
+/// `{{` and `}}` stand for literal braces.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutputTemplate(Vec<Part>);
+
+impl FromStr for OutputTemplate {
+    type Err = String;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars();
+
+        while let Some(ch) = chars.next() {
+            match ch {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let Some((name, rest)) = chars.as_str().split_once('}') else {
+                        return Err("unclosed `{`; write `{{` for a literal brace".to_string());
+                    };
+                    let field = match name {
+                        "file" => Field::File,
+                        "line" => Field::Line,
+                        "class" => Field::Class,
+                        "func" => Field::Func,
+                        "markers" => Field::Markers,
+                        _ => {
+                            return Err(format!(
+                                "unknown placeholder {{{}}}; expected one of {}",
+                                name, PLACEHOLDERS
+                            ));
+                        }
+                    };
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Field(field));
+                    chars = rest.chars();
+                }
+                '}' => return Err("unmatched `}`; write `}}` for a literal brace".to_string()),
+                ch => literal.push(ch),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        Ok(Self(parts))
+    }
+}
+
+impl OutputTemplate {
+    /// Write one rendered line per finding.
+    pub fn write(&self, out: &mut impl Write, findings: &[Finding]) -> io::Result<()> {
+        for finding in findings {
+            writeln!(out, "{}", self.render(finding))?;
+        }
+        Ok(())
+    }
+
+    fn render(&self, finding: &Finding) -> String {
+        self.0
+            .iter()
+            .map(|part| match part {
+                Part::Literal(literal) => literal.clone(),
+                Part::Field(Field::File) => finding.file.display().to_string(),
+                Part::Field(Field::Line) => finding.line.to_string(),
+                Part::Field(Field::Class) => finding.class_path.join("::"),
+                Part::Field(Field::Func) => finding.function.clone(),
+                Part::Field(Field::Markers) => {
+                    let markers: Vec<&str> = finding.markers.iter().map(String::as_str).collect();
+                    markers.join(",")
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_finding as finding;
+
+    fn render(template: &str, findings: &[Finding]) -> String {
+        let template: OutputTemplate = template.parse().unwrap();
+        let mut out = Vec::new();
+        template.write(&mut out, findings).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_placeholders() {
+        let mut method = finding("tests/test_x.py", 7, &["TestFoo", "TestNested"], "test_b");
+        method.markers = ["e2e", "flaky"].map(String::from).into();
+        let findings = [finding("tests/test_x.py", 3, &[], "test_a"), method];
+
+        assert_eq!(
+            render("{file}:{line}: {func}", &findings),
+            "tests/test_x.py:3: test_a\ntests/test_x.py:7: test_b\n"
+        );
+        assert_eq!(
+            render("{{{class}}} {func} [{markers}]", &findings[1..]),
+            "{TestFoo::TestNested} test_b [e2e,flaky]\n"
+        );
+    }
+
+    #[test]
+    fn test_invalid_templates() {
+        let err = "{file}:{lineno}".parse::<OutputTemplate>().unwrap_err();
+        assert!(err.contains("unknown placeholder {lineno}"), "{}", err);
+        assert!("{file".parse::<OutputTemplate>().is_err());
+        assert!("file}".parse::<OutputTemplate>().is_err());
+    }
+}
@@ -0,0 +1,2574 @@
+//! Line-based test discovery.
+//!
+//! Finds test functions with regular expressions in a single pass over the lines of a
+//! file, collecting the markers applied by the decorators preceding each definition. The
+//! source is fed line by line, so large files can be streamed instead of read at once.
+
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead};
+use std::sync::LazyLock;
+
+use crate::{MarkerSources, ParseOptions, TestFunction};
+
+/// Find test functions in `content` that carry none of `exclude_markers`.
+pub fn find_python_test_functions(
+    content: &str,
+    exclude_markers: &HashSet<String>,
+) -> Vec<TestFunction> {
+    find_python_test_functions_with_options(content, exclude_markers, &ParseOptions::default())
+}
+
+/// Like [`find_python_test_functions`], with control over which tests are recognized.
+pub fn find_python_test_functions_with_options(
+    content: &str,
+    exclude_markers: &HashSet<String>,
+    options: &ParseOptions,
+) -> Vec<TestFunction> {
+    collect_python_tests(content, options)
+        .into_iter()
+        .filter(|test| !test.has_any_marker(exclude_markers))
+        .collect()
+}
+
+/// Find all test functions in `content`, together with the markers applied to each.
+pub fn collect_python_tests(content: &str, options: &ParseOptions) -> Vec<TestFunction> {
+    let mut collector = Collector::new(options);
+    for line in content.lines() {
+        collector.push_line(line);
+    }
+    collector.finish()
+}
+
+/// Like [`collect_python_tests`], reading the source line by line from `reader`, so that
+/// only the decorators of the current definition are held in memory.
+///
+/// A leading UTF-8 byte order mark is skipped.
+pub fn collect_python_tests_from_reader(
+    mut reader: impl BufRead,
+    options: &ParseOptions,
+) -> io::Result<Vec<TestFunction>> {
+    let mut collector = Collector::new(options);
+    let mut line = String::new();
+
+    while reader.read_line(&mut line)? > 0 {
+        let mut content = line.strip_suffix('\n').unwrap_or(&line);
+        content = content.strip_suffix('\r').unwrap_or(content);
+        if collector.line_number == 0 {
+            content = content.strip_prefix('\u{feff}').unwrap_or(content);
+        }
+        collector.push_line(content);
+        line.clear();
+    }
+
+    Ok(collector.finish())
+}
+
+/// State of the scan over the lines of a file.
+///
+/// Markers applied by `pytestmark` assignments may follow the tests they apply to, so they
+/// are only resolved once all lines have been seen.
+struct Collector<'a> {
+    options: &'a ParseOptions,
+    test_fn_regex: Regex,
+    class_regex: Regex,
+    pytestmark_regex: Regex,
+    assignment_regex: Regex,
+    definition_regex: Regex,
+    function_regex: Regex,
+    masker: Masker,
+    /// Number of lines seen so far
+    line_number: usize,
+    /// Earlier physical lines of a backslash-continued logical line
+    continued_line: Option<String>,
+    /// Decorators seen since the last statement, outermost first
+    decorators: Vec<String>,
+    /// Whether a suppression comment was seen since the last statement
+    suppressed: bool,
+    /// Multi-line decorator being collected, and its bracket depth so far
+    open_decorator: Option<(String, i32)>,
+    /// Multi-line `pytestmark` value being collected: the class it applies to (or the
+    /// module), the value so far and its bracket depth
+    open_pytestmark: Option<(Option<usize>, String, i32)>,
+    /// Body of the last collected test, while its first statements may still skip it
+    open_body: Option<OpenBody>,
+    module_markers: HashSet<String>,
+    /// Module-level aliases of marks seen so far, e.g. `custom` for
+    /// `custom = pytest.mark.unit`, and the markers they apply
+    mark_aliases: HashMap<String, String>,
+    /// All classes seen so far, referenced by index
+    classes: Vec<ClassScope>,
+    /// Indices of the classes enclosing the current line, outermost first
+    class_stack: Vec<usize>,
+    /// Indentation of the outermost function enclosing the current line, if any
+    function_indent: Option<usize>,
+    tests: Vec<PendingTest>,
+}
+
+struct ClassScope {
+    name: String,
+    indent: usize,
+    /// Indentation of the statements in the class body, once the first one is seen
+    body_indent: Option<usize>,
+    is_test_class: bool,
+    /// Names of the base classes listed on the `class` line, without keyword arguments
+    bases: Vec<String>,
+    markers: HashSet<String>,
+}
+
+/// The start of a test function body, checked for `pytest.skip(...)` calls.
+struct OpenBody {
+    /// Index of the test in `Collector::tests`
+    test: usize,
+    def_indent: usize,
+    /// Indentation of the statements in the body, once the first one is seen
+    body_indent: Option<usize>,
+    /// Bracket depth at the end of the last line, starting with the signature
+    depth: i32,
+    /// Whether the `:` ending the signature is yet to be seen
+    in_signature: bool,
+    /// Number of top-level statements seen so far
+    statements: usize,
+}
+
+/// A test whose module and class markers are filled in once the whole file is seen.
+struct PendingTest {
+    name: String,
+    line: usize,
+    classes: Vec<usize>,
+    function_markers: HashSet<String>,
+    cases: Option<usize>,
+}
+
+impl<'a> Collector<'a> {
+    fn new(options: &'a ParseOptions) -> Self {
+        // Regex to match test functions, sync or async (allow whitespace at start)
+        let prefixes: Vec<String> = options
+            .test_prefixes
+            .iter()
+            .map(|prefix| regex::escape(prefix))
+            .collect();
+        let test_fn_regex = Regex::new(&format!(
+            r"^(\s*)(?:async\s+)?def\s+((?:{})\w+)\s*\(",
+            prefixes.join("|")
+        ))
+        .unwrap();
+
+        Self {
+            options,
+            test_fn_regex,
+            // Regex to match class definitions and their bases, if listed on the same line
+            class_regex: Regex::new(r"^(\s*)class\s+(\w+)\s*(?:\(([^)]*)\))?").unwrap(),
+            pytestmark_regex: Regex::new(r"^pytestmark\s*(?::[^=]*)?=([^=].*)$").unwrap(),
+            assignment_regex: Regex::new(r"^(\w+)\s*(?::[^=]*)?=([^=].*)$").unwrap(),
+            definition_regex: Regex::new(r"^(?:async\s+)?(?:def|class)\s").unwrap(),
+            function_regex: Regex::new(r"^\s*(?:async\s+)?def\s").unwrap(),
+            masker: Masker::default(),
+            line_number: 0,
+            continued_line: None,
+            decorators: Vec::new(),
+            suppressed: false,
+            open_decorator: None,
+            open_pytestmark: None,
+            open_body: None,
+            module_markers: HashSet::new(),
+            mark_aliases: HashMap::new(),
+            classes: Vec::new(),
+            class_stack: Vec::new(),
+            function_indent: None,
+            tests: Vec::new(),
+        }
+    }
+
+    /// Process the next line of the file, without its line terminator.
+    fn push_line(&mut self, line: &str) {
+        self.line_number += 1;
+        let in_string = self.masker.string.is_some();
+        // Comments are masked below, so look for suppression comments first
+        if !in_string && is_suppression_comment(line.trim()) {
+            self.suppressed = true;
+        }
+        // Match against code only, so that `def test_...` inside strings or comments is
+        // ignored
+        let line = self.masker.mask_line(line);
+
+        if let Some((_, value, depth)) = &mut self.open_pytestmark {
+            value.push_str(&line);
+            *depth += bracket_balance(&line);
+            if *depth <= 0 {
+                let (class, value, _) = self.open_pytestmark.take().unwrap();
+                self.add_pytestmark(class, &value);
+            }
+        }
+
+        if line.trim().is_empty() {
+            return;
+        }
+
+        let indent = indent_of(&line, self.options.tab_width);
+        if self.options.detect_body_skips {
+            self.check_body_skip(&line, indent, in_string);
+        }
+
+        // Any statement at or left of a class's indentation ends its body
+        while self
+            .class_stack
+            .last()
+            .is_some_and(|&class| self.classes[class].indent >= indent)
+        {
+            self.class_stack.pop();
+        }
+        if let Some(&class) = self.class_stack.last() {
+            self.classes[class].body_indent.get_or_insert(indent);
+        }
+
+        // Likewise for a function; like pytest, nothing defined within one is collected
+        if self
+            .function_indent
+            .is_some_and(|function| function >= indent)
+        {
+            self.function_indent = None;
+        }
+        if self.function_indent.is_some() {
+            self.track_decorators(line);
+            return;
+        }
+
+        self.check_pytestmark(&line, indent);
+        if indent == 0 {
+            self.check_mark_alias(&line);
+        }
+
+        if let Some(captures) = self.class_regex.captures(&line) {
+            let name = captures.get(2).unwrap().as_str();
+            let bases: Vec<&str> = captures
+                .get(3)
+                .map(|bases| bases.as_str().split(',').map(str::trim).collect())
+                .unwrap_or_default();
+            let decorators = std::mem::take(&mut self.decorators);
+
+            self.classes.push(ClassScope {
+                name: name.to_string(),
+                indent,
+                body_indent: None,
+                is_test_class: is_test_class(name, &bases, self.options),
+                bases: bases
+                    .iter()
+                    .filter(|base| !base.contains('='))
+                    .map(|base| base.to_string())
+                    .collect(),
+                markers: decorator_markers(&decorators, &self.mark_aliases, self.options),
+            });
+            self.class_stack.push(self.classes.len() - 1);
+        } else if let Some(captures) = self.test_fn_regex.captures(&line) {
+            let name = captures.get(2).unwrap().as_str().to_string();
+            let decorators = std::mem::take(&mut self.decorators);
+            let suppressed = std::mem::take(&mut self.suppressed);
+            if !suppressed && self.is_collected(&decorators) {
+                self.open_body = Some(OpenBody {
+                    test: self.tests.len(),
+                    def_indent: indent,
+                    body_indent: None,
+                    depth: 0,
+                    in_signature: true,
+                    statements: 0,
+                });
+                self.tests.push(PendingTest {
+                    name,
+                    line: self.line_number,
+                    classes: self.class_stack.clone(),
+                    function_markers: decorator_markers(
+                        &decorators,
+                        &self.mark_aliases,
+                        self.options,
+                    ),
+                    cases: parametrize_cases(decorators.iter().map(String::as_str)),
+                });
+                if self.options.detect_body_skips {
+                    self.check_signature(&line);
+                }
+            }
+        }
+
+        if self.function_regex.is_match(&line) {
+            self.function_indent = Some(indent);
+        }
+        self.track_decorators(line);
+    }
+
+    /// Whether a function with a test name and the given decorators, defined on the
+    /// current line, is collected.
+    fn is_collected(&self, decorators: &[String]) -> bool {
+        // Fixtures are never collected as tests, whatever their name
+        if decorators.iter().any(|line| is_fixture_decorator(line)) {
+            return false;
+        }
+
+        !(self.options.skip_non_instance_methods
+            && !self.class_stack.is_empty()
+            && decorators
+                .iter()
+                .any(|line| is_non_instance_method_decorator(line)))
+    }
+
+    /// Mark the last collected test as skipped if one of the first top-level statements of
+    /// its body calls `pytest.skip(...)`; statements nested in conditionals don't count.
+    fn check_body_skip(&mut self, line: &str, indent: usize, in_string: bool) {
+        let Some(body) = &mut self.open_body else {
+            return;
+        };
+        if body.in_signature {
+            self.check_signature(line);
+            return;
+        }
+        let continued = in_string || body.depth > 0;
+        body.depth += bracket_balance(line);
+        if continued {
+            // The rest of the signature, or of a statement spanning several lines
+            return;
+        }
+        if indent <= body.def_indent {
+            self.open_body = None;
+            return;
+        }
+        if *body.body_indent.get_or_insert(indent) != indent {
+            return;
+        }
+        self.check_body_statement(line.trim());
+    }
+
+    /// Read the signature of the open body up to the `:` ending it, treating the rest of
+    /// that line as the first statement of the body, e.g. in `def test_a(): pytest.skip()`.
+    fn check_signature(&mut self, line: &str) {
+        let Some(body) = &mut self.open_body else {
+            return;
+        };
+        for (i, ch) in line.char_indices() {
+            match ch {
+                '(' | '[' | '{' => body.depth += 1,
+                ')' | ']' | '}' => body.depth -= 1,
+                ':' if body.depth == 0 => {
+                    body.in_signature = false;
+                    let rest = line[i + 1..].trim();
+                    body.depth = bracket_balance(rest);
+                    if !rest.is_empty() && !rest.starts_with('#') {
+                        self.check_body_statement(rest);
+                    }
+                    return;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Count a top-level statement of the open body, marking its test as skipped if it
+    /// calls `pytest.skip(...)`.
+    fn check_body_statement(&mut self, statement: &str) {
+        let Some(body) = &mut self.open_body else {
+            return;
+        };
+        body.statements += 1;
+        let test = body.test;
+        if body.statements >= BODY_SKIP_STATEMENTS {
+            self.open_body = None;
+        }
+        if is_skip_call(statement) && !self.options.ignored_markers.contains("skip") {
+            self.tests[test].function_markers.insert("skip".to_string());
+            self.open_body = None;
+        }
+    }
+
+    /// Collect `pytestmark = ...` assignments made directly in the module or the body of
+    /// the innermost class.
+    ///
+    /// Handles both a single mark (`pytest.mark.slow`) and a list of marks, including
+    /// lists spanning multiple lines.
+    fn check_pytestmark(&mut self, line: &str, indent: usize) {
+        let class = self.class_stack.last().copied();
+        let applies = match class {
+            _ if indent == 0 => Some(None),
+            Some(class) if self.classes[class].body_indent == Some(indent) => Some(Some(class)),
+            _ => None,
+        };
+        let Some(target) = applies else {
+            return;
+        };
+        let Some(captures) = self.pytestmark_regex.captures(line.trim()) else {
+            return;
+        };
+
+        // Join continuation lines until the brackets of the assigned value balance
+        let value = captures.get(1).unwrap().as_str().to_string();
+        let depth = bracket_balance(&value);
+        if depth > 0 {
+            self.open_pytestmark = Some((target, value, depth));
+        } else {
+            self.add_pytestmark(target, &value);
+        }
+    }
+
+    /// Track module-level assignments of a mark to a name, e.g. `custom = pytest.mark.unit`,
+    /// so that `@custom` applies `unit`; assigning anything else to the name unbinds it.
+    fn check_mark_alias(&mut self, line: &str) {
+        let Some(captures) = self.assignment_regex.captures(line.trim()) else {
+            return;
+        };
+        let name = captures.get(1).unwrap().as_str().to_string();
+        match mark_alias_target(captures.get(2).unwrap().as_str()) {
+            Some(marker) => self.mark_aliases.insert(name, marker),
+            None => self.mark_aliases.remove(&name),
+        };
+    }
+
+    fn add_pytestmark(&mut self, class: Option<usize>, value: &str) {
+        let markers = extract_pytestmark_value_markers(value)
+            .into_iter()
+            .filter(|marker| !self.options.ignored_markers.contains(marker));
+        match class {
+            Some(class) => self.classes[class].markers.extend(markers),
+            None => self.module_markers.extend(markers),
+        }
+    }
+
+    /// Track the decorators preceding the next definition.
+    ///
+    /// Backslash-continued lines are joined, and the lines of a multi-line decorator are
+    /// collected until its brackets balance.
+    fn track_decorators(&mut self, line: String) {
+        let line = line.trim();
+        if let Some(continued) = line.strip_suffix('\\') {
+            let joined = self.continued_line.get_or_insert_with(String::new);
+            joined.push_str(continued);
+            joined.push(' ');
+            return;
+        }
+        let line = match self.continued_line.take() {
+            Some(continued) => continued + line,
+            None => line.to_string(),
+        };
+
+        if self.definition_regex.is_match(&line) {
+            // Decorators never span a definition, even if their brackets don't seem to
+            // balance
+            if let Some((decorator, _)) = self.open_decorator.take() {
+                self.decorators.push(decorator);
+            }
+            // The definition took the decorators preceding it, if it was of interest
+            self.decorators.clear();
+            self.suppressed = false;
+            return;
+        }
+
+        if let Some((decorator, depth)) = &mut self.open_decorator {
+            decorator.push(' ');
+            decorator.push_str(&line);
+            *depth += bracket_balance(&line);
+            if *depth <= 0 {
+                let (decorator, _) = self.open_decorator.take().unwrap();
+                self.decorators.push(decorator);
+            }
+        } else if line.starts_with('@') {
+            let depth = bracket_balance(&line);
+            if depth > 0 {
+                self.open_decorator = Some((line, depth));
+            } else {
+                self.decorators.push(line);
+            }
+        } else {
+            // Any other statement separates decorators from the next definition
+            self.decorators.clear();
+            self.suppressed = false;
+        }
+    }
+
+    fn finish(self) -> Vec<TestFunction> {
+        let options = self.options;
+        let classes = self.classes;
+        let module_markers = self.module_markers;
+
+        // Like pytest, classes inherit the markers of their base classes; only bases
+        // defined earlier in the same file can be followed
+        let mut class_markers: Vec<HashSet<String>> = Vec::with_capacity(classes.len());
+        for (index, class) in classes.iter().enumerate() {
+            let mut markers = class.markers.clone();
+            for base in &class.bases {
+                if let Some(base) = classes[..index].iter().rposition(|c| &c.name == base) {
+                    markers.extend(class_markers[base].iter().cloned());
+                }
+            }
+            class_markers.push(markers);
+        }
+
+        self.tests
+            .into_iter()
+            .map(|test| {
+                // Markers of the module and all enclosing test classes apply as well
+                let marker_sources = MarkerSources {
+                    module: options.resolve_markers(module_markers.iter().cloned()),
+                    class: options.resolve_markers(
+                        test.classes
+                            .iter()
+                            .filter(|&&class| classes[class].is_test_class)
+                            .flat_map(|&class| class_markers[class].iter().cloned()),
+                    ),
+                    function: options.resolve_markers(test.function_markers),
+                };
+
+                TestFunction::new(
+                    test.name,
+                    test.classes
+                        .iter()
+                        .map(|&class| classes[class].name.clone())
+                        .collect(),
+                    test.line,
+                    marker_sources,
+                    test.cases,
+                )
+            })
+            .collect()
+    }
+}
+
+/// Number of statements at the start of a test body checked for `pytest.skip(...)` calls.
+pub(crate) const BODY_SKIP_STATEMENTS: usize = 3;
+
+/// Whether the statement `line` calls `pytest.skip(...)`.
+fn is_skip_call(line: &str) -> bool {
+    static SKIP_REGEX: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"^pytest\.skip\s*\(").unwrap());
+    SKIP_REGEX.is_match(line)
+}
+
+/// Whether a class with the given name and bases is a test class, whose markers apply to
+/// the tests within it.
+pub(crate) fn is_test_class(name: &str, bases: &[&str], options: &ParseOptions) -> bool {
+    options
+        .class_prefixes
+        .iter()
+        .any(|prefix| name.starts_with(prefix.as_str()))
+        || (options.include_testcase
+            && bases
+                .iter()
+                .any(|base| base.rsplit('.').next().unwrap().ends_with("TestCase")))
+}
+
+/// Collect the markers applied by `decorators`, each joined into a single line.
+fn decorator_markers(
+    decorators: &[String],
+    mark_aliases: &HashMap<String, String>,
+    options: &ParseOptions,
+) -> HashSet<String> {
+    decorators
+        .iter()
+        .filter(|line| !is_ignored_decorator(line, options))
+        .flat_map(|line| {
+            let mut markers = match aliased_marker(line, mark_aliases) {
+                Some(marker) => vec![marker.clone()],
+                None => extract_markers(line, options.strict_marks),
+            };
+            if options.param_marks {
+                markers.extend(parametrize_case_markers(line));
+            }
+            markers
+        })
+        .filter(|marker| !options.ignored_markers.contains(marker))
+        .collect()
+}
+
+/// The marker a mark assigned to a name applies, e.g. `unit` for the value of
+/// `custom = pytest.mark.unit` or `slow_db = pytest.mark.skipif(...)`.
+pub(crate) fn mark_alias_target(value: &str) -> Option<String> {
+    static MARK_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"(?s)^\s*(?:pytest\s*\.\s*)?mark\s*\.\s*(\w+)\s*(?:\(.*)?$").unwrap()
+    });
+    MARK_REGEX
+        .captures(value)
+        .map(|captures| captures.get(1).unwrap().as_str().to_string())
+}
+
+/// The marker applied by a decorator line that uses an alias of a mark, e.g. `unit` for
+/// `@custom` after `custom = pytest.mark.unit`.
+pub(crate) fn aliased_marker<'a>(
+    decorator_line: &str,
+    mark_aliases: &'a HashMap<String, String>,
+) -> Option<&'a String> {
+    static NAME_REGEX: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"(?s)^@\s*(\w+)\s*(?:\(.*)?$").unwrap());
+    let captures = NAME_REGEX.captures(decorator_line)?;
+    mark_aliases.get(captures.get(1).unwrap().as_str())
+}
+
+/// Whether a decorator line applies one of the decorators that never apply markers, e.g.
+/// `@mock.patch("x")` with `mock.patch` ignored.
+pub(crate) fn is_ignored_decorator(decorator_line: &str, options: &ParseOptions) -> bool {
+    static NAME_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^@\s*([\w.]+)").unwrap());
+    if options.ignored_decorators.is_empty() {
+        return false;
+    }
+    NAME_REGEX.captures(decorator_line).is_some_and(|captures| {
+        let name = captures.get(1).unwrap().as_str();
+        options
+            .ignored_decorators
+            .iter()
+            .any(|ignored| ignored == name)
+    })
+}
+
+/// Markers applied to every case of a `parametrize` decorator through
+/// `pytest.param(..., marks=...)`, e.g. `slow` for
+/// `@pytest.mark.parametrize("x", [pytest.param(1, marks=pytest.mark.slow)])`.
+///
+/// Expects string contents to be masked. A case that isn't a `pytest.param` carries no
+/// markers, so a decorator with any such case applies none.
+pub(crate) fn parametrize_case_markers(decorator: &str) -> HashSet<String> {
+    static PARAM_REGEX: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"^(?:pytest\.)?param\s*\(").unwrap());
+    static MARKS_REGEX: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"(?s)\bmarks\s*=(.*)$").unwrap());
+
+    let Some(argvalues) = parametrize_argvalues(decorator) else {
+        return HashSet::new();
+    };
+
+    let mut shared: Option<HashSet<String>> = None;
+    for case in split_top_level(enclosed(argvalues)) {
+        let markers: HashSet<String> = if PARAM_REGEX.is_match(case) {
+            MARKS_REGEX
+                .captures(enclosed(case))
+                .map(|captures| extract_pytestmark_value_markers(&captures[1]))
+                .unwrap_or_default()
+        } else {
+            HashSet::new()
+        };
+        shared = Some(match shared {
+            Some(shared) => &shared & &markers,
+            None => markers,
+        });
+    }
+
+    shared.unwrap_or_default()
+}
+
+/// Number of cases the `parametrize` decorators among `decorators` expand a test into, e.g.
+/// 6 for two stacked decorators with 2 and 3 cases; expects masked text.
+///
+/// Only list and tuple literals can be counted. `None` is returned if any of the
+/// decorators has cases computed at runtime, or none of them is a `parametrize` decorator.
+pub(crate) fn parametrize_cases<'a>(
+    decorators: impl IntoIterator<Item = &'a str>,
+) -> Option<usize> {
+    let mut cases = None;
+    for decorator in decorators {
+        if !decorator.contains("parametrize(") {
+            continue;
+        }
+        // The literal must make up the whole argument, not e.g. `[1, 2] + OTHER_CASES`
+        let argvalues = parametrize_argvalues(decorator)?;
+        let values = enclosed(argvalues);
+        if values.len() + 2 != argvalues.len() {
+            return None;
+        }
+        cases = Some(cases.unwrap_or(1) * split_top_level(values).len());
+    }
+    cases
+}
+
+/// The `argvalues` argument of a `parametrize` decorator, if it is a list or tuple literal.
+fn parametrize_argvalues(decorator: &str) -> Option<&str> {
+    static KEYWORD_REGEX: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"(?s)^(\w+)\s*=([^=].*)$").unwrap());
+
+    let start = decorator.find("parametrize(")?;
+    let arguments = enclosed(&decorator[start + "parametrize".len()..]);
+    let arguments = split_top_level(arguments);
+
+    // `argvalues` is the second positional argument, unless passed by keyword
+    let keyword = arguments.iter().find_map(|argument| {
+        KEYWORD_REGEX
+            .captures(argument)
+            .filter(|captures| &captures[1] == "argvalues")
+            .map(|captures| captures.get(2).unwrap().as_str().trim())
+    });
+    let positional = arguments
+        .get(1)
+        .filter(|argument| !KEYWORD_REGEX.is_match(argument))
+        .copied();
+
+    keyword
+        .or(positional)
+        .filter(|argvalues| argvalues.starts_with(['[', '(']))
+}
+
+/// Text within the first bracket pair of `text`, e.g. `a, (b)` for `f(a, (b)) + c`.
+fn enclosed(text: &str) -> &str {
+    let Some(open) = text.find(['(', '[', '{']) else {
+        return "";
+    };
+
+    let mut depth = 0;
+    for (i, ch) in text.char_indices().skip_while(|(i, _)| *i < open) {
+        depth += bracket_balance(ch.encode_utf8(&mut [0; 4]));
+        if depth == 0 {
+            return &text[open + 1..i];
+        }
+    }
+    &text[open + 1..]
+}
+
+/// Split `text` at commas outside of brackets, dropping blank parts such as after a
+/// trailing comma.
+fn split_top_level(text: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, ch) in text.char_indices() {
+        match ch {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(text[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(text[start..].trim());
+
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+/// Whether a decorator line makes a method a static or class method.
+pub(crate) fn is_non_instance_method_decorator(decorator_line: &str) -> bool {
+    static METHOD_REGEX: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"^@(?:staticmethod|classmethod)\b").unwrap());
+    METHOD_REGEX.is_match(decorator_line)
+}
+
+/// Whether a comment line, e.g. `# collect-unmarked: ignore`, suppresses reporting the test
+/// defined below it.
+pub(crate) fn is_suppression_comment(line: &str) -> bool {
+    static SUPPRESSION_REGEX: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"^#\s*collect-unmarked:\s*ignore\b").unwrap());
+    line.starts_with('#') && SUPPRESSION_REGEX.is_match(line)
+}
+
+/// Whether a decorator line declares a pytest fixture, e.g. `@pytest.fixture(scope="module")`.
+pub(crate) fn is_fixture_decorator(decorator_line: &str) -> bool {
+    static FIXTURE_REGEX: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"^@(?:pytest\.)?fixture\b").unwrap());
+    FIXTURE_REGEX.is_match(decorator_line)
+}
+
+/// Remove comments and blank out the contents of string literals.
+///
+/// Line structure and string delimiters are kept intact, so line numbers, indentation
+/// and bracket depth outside of strings are unaffected.
+pub(crate) fn mask_strings_and_comments(content: &str) -> String {
+    let mut masker = Masker::default();
+    content
+        .split('\n')
+        .map(|line| masker.mask_line(line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Masks strings and comments line by line, carrying strings that span lines over.
+#[derive(Default)]
+struct Masker {
+    /// Quote character of the open string and whether it is triple-quoted
+    string: Option<(char, bool)>,
+}
+
+impl Masker {
+    fn mask_line(&mut self, line: &str) -> String {
+        let chars: Vec<char> = line.chars().collect();
+        let mut masked = String::with_capacity(line.len());
+        let mut escaped_line_break = false;
+        let mut i = 0;
+
+        let is_triple = |i: usize, quote: char| {
+            chars.get(i + 1) == Some(&quote) && chars.get(i + 2) == Some(&quote)
+        };
+
+        while i < chars.len() {
+            let ch = chars[i];
+            match self.string {
+                None => match ch {
+                    '#' => break,
+                    '\'' | '"' => {
+                        let triple = is_triple(i, ch);
+                        let delimiter_len = if triple { 3 } else { 1 };
+                        masked.extend(std::iter::repeat_n(ch, delimiter_len));
+                        i += delimiter_len - 1;
+                        self.string = Some((ch, triple));
+                    }
+                    _ => masked.push(ch),
+                },
+                Some((quote, triple)) => {
+                    if ch == '\\' {
+                        // An escaped character never terminates the string
+                        masked.push(' ');
+                        if i + 1 < chars.len() {
+                            masked.push(' ');
+                            i += 1;
+                        } else {
+                            escaped_line_break = true;
+                        }
+                    } else if ch == quote && (!triple || is_triple(i, quote)) {
+                        let delimiter_len = if triple { 3 } else { 1 };
+                        masked.extend(std::iter::repeat_n(ch, delimiter_len));
+                        i += delimiter_len - 1;
+                        self.string = None;
+                    } else {
+                        masked.push(' ');
+                    }
+                }
+            }
+            i += 1;
+        }
+
+        // An unterminated single-quoted string ends at the line break
+        if matches!(self.string, Some((_, false))) && !escaped_line_break {
+            self.string = None;
+        }
+
+        masked
+    }
+}
+
+/// Column of the first non-whitespace character, with tabs advancing to the next
+/// multiple of `tab_width` as in Python's tokenizer.
+fn indent_of(line: &str, tab_width: usize) -> usize {
+    let mut column = 0;
+    for ch in line.chars().take_while(|ch| ch.is_whitespace()) {
+        if ch == '\t' {
+            column += tab_width - column % tab_width;
+        } else {
+            column += 1;
+        }
+    }
+    column
+}
+
+/// Extract marker names from the value assigned to `pytestmark`.
+pub(crate) fn extract_pytestmark_value_markers(value: &str) -> HashSet<String> {
+    static MARK_REGEX: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"\bmark\s*\.\s*(\w+)").unwrap());
+
+    MARK_REGEX
+        .captures_iter(value)
+        .map(|captures| captures.get(1).unwrap().as_str().to_string())
+        .collect()
+}
+
+fn bracket_balance(text: &str) -> i32 {
+    text.chars()
+        .map(|ch| match ch {
+            '(' | '[' | '{' => 1,
+            ')' | ']' | '}' => -1,
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Extract the marker name applied by a decorator line, e.g. `unit` from `@pytest.mark.unit`.
+pub fn extract_pytest_marker(decorator_line: &str) -> Option<String> {
+    extract_pytest_markers(decorator_line).into_iter().next()
+}
+
+/// Extract the names of all markers applied on a decorator line, e.g. `unit` and `slow`
+/// from `@pytest.mark.unit @pytest.mark.slow`.
+pub fn extract_pytest_markers(decorator_line: &str) -> Vec<String> {
+    extract_markers(decorator_line, false)
+}
+
+/// Like [`extract_pytest_markers`]; with `strict_marks`, only `@pytest.mark.*` and `@mark.*`
+/// decorators apply markers, rather than any decorator such as `@requires_db`.
+pub(crate) fn extract_markers(decorator_line: &str, strict_marks: bool) -> Vec<String> {
+    // Handle various pytest marker formats:
+    // @pytest.mark.unit
+    // @pytest.mark.parametrize(...)
+    // @pytest.mark.skip
+    // @mark.unit (after `from pytest import mark`)
+    // @unit (any other decorator, unless `strict_marks`)
+    // @pytest . mark . unit (Python allows whitespace around the dots)
+
+    static STRICT_MARKER_REGEX: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"@\s*(?:pytest\s*\.\s*)?mark\s*\.\s*(\w+)").unwrap());
+    static MARKER_REGEX: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"@\s*(?:(?:pytest\s*\.\s*)?mark\s*\.\s*)?(\w+)").unwrap());
+    let marker_regex = if strict_marks {
+        &STRICT_MARKER_REGEX
+    } else {
+        &MARKER_REGEX
+    };
+
+    // An `@` within the arguments of a called marker, e.g. in
+    // `@pytest.mark.skip(reason="see @bob")`, doesn't start another decorator
+    marker_regex
+        .captures_iter(decorator_line)
+        .filter(|captures| {
+            bracket_balance(&decorator_line[..captures.get(0).unwrap().start()]) <= 0
+        })
+        .map(|captures| captures.get(1).unwrap().as_str().to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    fn names(tests: &[TestFunction]) -> Vec<&str> {
+        tests.iter().map(|t| t.name.as_str()).collect()
+    }
+
+    /// Fail unless the AST parser collects the same tests from `content` as this one.
+    fn assert_parsers_agree(content: &str, options: &ParseOptions) {
+        assert_eq!(
+            crate::ast_parser::collect_python_tests(content, options).unwrap(),
+            collect_python_tests(content, options)
+        );
+    }
+
+    #[test]
+    fn test_extract_pytest_marker() {
+        assert_eq!(
+            extract_pytest_marker("@pytest.mark.unit"),
+            Some("unit".to_string())
+        );
+        assert_eq!(
+            extract_pytest_marker("@pytest.mark.slow"),
+            Some("slow".to_string())
+        );
+        assert_eq!(extract_pytest_marker("@unit"), Some("unit".to_string()));
+        assert_eq!(extract_pytest_marker("@skip"), Some("skip".to_string()));
+        assert_eq!(
+            extract_pytest_marker("@pytest.mark.parametrize('x', [1, 2])"),
+            Some("parametrize".to_string())
+        );
+        assert_eq!(
+            extract_pytest_marker("@mark.unit"),
+            Some("unit".to_string())
+        );
+        assert_eq!(
+            extract_pytest_marker("@mark.slow"),
+            Some("slow".to_string())
+        );
+        assert_eq!(
+            extract_pytest_marker("@mark.skip"),
+            Some("skip".to_string())
+        );
+    }
+
+    #[test]
+    fn test_extract_marker_with_whitespace_around_dots() {
+        assert_eq!(
+            extract_pytest_marker("@pytest . mark . slow"),
+            Some("slow".to_string())
+        );
+        assert_eq!(
+            extract_pytest_marker("@pytest.mark .unit(reason='x')"),
+            Some("unit".to_string())
+        );
+        assert_eq!(extract_markers("@ mark. slow", true), vec!["slow"]);
+
+        let content = r#"
+pytestmark = pytest . mark . integration
+
+@pytest . mark . slow
+def test_spaced():
+    pass
+"#;
+        let tests = collect_python_tests(content, &ParseOptions::default());
+        assert_eq!(
+            tests[0].markers,
+            BTreeSet::from(["integration".to_string(), "slow".to_string()])
+        );
+        assert_parsers_agree(content, &ParseOptions::default());
+    }
+
+    #[test]
+    fn test_extract_markers_strict() {
+        for decorator in [
+            "@pytest.mark.unit",
+            "@mark.unit",
+            "@pytest.mark.unit(reason='x')",
+        ] {
+            assert_eq!(extract_markers(decorator, false), vec!["unit"]);
+            assert_eq!(extract_markers(decorator, true), vec!["unit"]);
+        }
+        for decorator in ["@requires_db", "@requires_db(engine='pg')", "@unit"] {
+            assert_eq!(extract_markers(decorator, false).len(), 1);
+            assert!(extract_markers(decorator, true).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_strict_marks() {
+        let content = r#"
+import pytest
+
+@requires_db
+def test_custom_decorator():
+    pass
+
+@pytest.mark.requires_db
+def test_marked():
+    pass
+"#;
+        let exclude_markers = HashSet::from(["requires_db".to_string()]);
+
+        let result = find_python_test_functions(content, &exclude_markers);
+        assert!(result.is_empty());
+
+        let options = ParseOptions {
+            strict_marks: true,
+            ..ParseOptions::default()
+        };
+        let result = find_python_test_functions_with_options(content, &exclude_markers, &options);
+        assert_eq!(names(&result), vec!["test_custom_decorator"]);
+        assert_parsers_agree(content, &options);
+    }
+
+    #[test]
+    fn test_ignored_decorators() {
+        let content = r#"
+import pytest
+from unittest import mock
+
+@pytest.mark.unit
+@mock.patch("os.getcwd")
+@freeze_time("2024-01-01")
+def test_marked(getcwd):
+    pass
+
+@mock.patch("os.getcwd")
+def test_patched(getcwd):
+    pass
+
+@mock.patch.object(Foo, "bar")
+def test_patched_object(bar):
+    pass
+"#;
+        let exclude_markers = HashSet::from(["unit".to_string()]);
+
+        let result = collect_python_tests(content, &ParseOptions::default());
+        assert_eq!(result[1].markers, BTreeSet::from(["mock".to_string()]));
+
+        let options = ParseOptions {
+            ignored_decorators: vec!["mock.patch".to_string(), "freeze_time".to_string()],
+            ..ParseOptions::default()
+        };
+        let result = collect_python_tests(content, &options);
+        assert_eq!(result[0].markers, BTreeSet::from(["unit".to_string()]));
+        assert!(result[1].markers.is_empty());
+        // Only the named decorator is ignored, not others sharing its prefix
+        assert_eq!(result[2].markers, BTreeSet::from(["mock".to_string()]));
+
+        let result = find_python_test_functions_with_options(content, &exclude_markers, &options);
+        assert_eq!(names(&result), vec!["test_patched", "test_patched_object"]);
+        assert_parsers_agree(content, &options);
+    }
+
+    #[test]
+    fn test_extract_pytest_markers() {
+        assert_eq!(
+            extract_pytest_markers("@pytest.mark.unit @pytest.mark.slow"),
+            vec!["unit".to_string(), "slow".to_string()]
+        );
+        assert_eq!(
+            extract_pytest_markers("@pytest.mark.integration"),
+            vec!["integration".to_string()]
+        );
+        assert!(extract_pytest_markers("def test_x():").is_empty());
+    }
+
+    #[test]
+    fn test_extract_called_markers() {
+        for decorator in [
+            "@pytest.mark.unit()",
+            "@pytest.mark.unit(reason=\"x\")",
+            "@pytest.mark.unit(1, key=[2, 3])",
+            "@pytest.mark.unit (reason=\"x\")",
+            "@pytest.mark.unit(",
+            "@pytest.mark.unit[0]",
+            "@mark.unit(reason=\"x\")",
+        ] {
+            assert_eq!(
+                extract_pytest_markers(decorator),
+                vec!["unit".to_string()],
+                "{}",
+                decorator
+            );
+        }
+
+        assert_eq!(
+            extract_pytest_markers("@pytest.mark.skip(reason=\"ask @bob\") @pytest.mark.slow"),
+            vec!["skip".to_string(), "slow".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_called_markers_with_arguments() {
+        let content = r#"
+import pytest
+
+@pytest.mark.unit()
+def test_empty_call():
+    pass
+
+@pytest.mark.unit(reason="x")
+def test_keyword_argument():
+    pass
+
+@pytest.mark.unit(
+    reason="spans lines",
+    run=False,
+)
+def test_multiline_call():
+    pass
+
+@pytest.mark.skip(reason="ask @bob")
+def test_at_in_argument():
+    pass
+
+def test_unmarked():
+    pass
+"#;
+
+        let result = collect_python_tests(content, &ParseOptions::default());
+        let markers: Vec<(&str, Vec<&str>)> = result
+            .iter()
+            .map(|test| {
+                (
+                    test.name.as_str(),
+                    test.markers.iter().map(String::as_str).collect(),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            markers,
+            vec![
+                ("test_empty_call", vec!["unit"]),
+                ("test_keyword_argument", vec!["unit"]),
+                ("test_multiline_call", vec!["unit"]),
+                ("test_at_in_argument", vec!["skip"]),
+                ("test_unmarked", vec![]),
+            ]
+        );
+        assert_parsers_agree(content, &ParseOptions::default());
+    }
+
+    #[test]
+    fn test_multiple_markers_on_one_line() {
+        let content = r#"
+import pytest
+
+@pytest.mark.slow @pytest.mark.unit
+def test_stacked():
+    pass
+
+def test_unmarked():
+    pass
+"#;
+
+        let exclude_markers: HashSet<String> = ["unit"].iter().map(|s| s.to_string()).collect();
+        let result = find_python_test_functions(content, &exclude_markers);
+
+        assert_eq!(names(&result), vec!["test_unmarked"]);
+    }
+
+    #[test]
+    fn test_find_python_test_functions() {
+        let content = r#"
+import pytest
+
+@pytest.mark.unit
+def test_marked_function():
+    pass
+
+def test_unmarked_function():
+    pass
+
+@pytest.mark.skip
+def test_skipped_function():
+    pass
+
+def test_another_unmarked():
+    pass
+"#;
+
+        let exclude_markers: HashSet<String> =
+            ["unit", "skip"].iter().map(|s| s.to_string()).collect();
+        let result = find_python_test_functions(content, &exclude_markers);
+
+        assert_eq!(
+            names(&result),
+            vec!["test_unmarked_function", "test_another_unmarked"]
+        );
+    }
+
+    #[test]
+    fn test_multiline_decorator() {
+        let content = r#"
+import pytest
+
+@pytest.mark.unit
+@pytest.mark.parametrize(
+    "arg1, arg2",
+    [
+        pytest.param("a", "b"),
+        pytest.param("c", "d"),
+    ],
+)
+def test_with_multiline_decorator():
+    pass
+
+def test_unmarked():
+    pass
+"#;
+
+        let exclude_markers: HashSet<String> = ["unit"].iter().map(|s| s.to_string()).collect();
+        let result = find_python_test_functions(content, &exclude_markers);
+
+        assert_eq!(names(&result), vec!["test_unmarked"]);
+    }
+
+    #[test]
+    fn test_class_methods() {
+        let content = r#"
+import pytest
+
+class TestExample:
+    @pytest.mark.unit
+    def test_marked_method(self):
+        pass
+
+    def test_unmarked_method(self):
+        pass
+
+    @pytest.mark.integration
+    def test_another_marked_method(self):
+        pass
+
+def test_function_level():
+    pass
+
+class TestAnother:
+    def test_unmarked_in_class(self):
+        pass
+"#;
+
+        let exclude_markers: HashSet<String> = ["unit", "integration"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let result = find_python_test_functions(content, &exclude_markers);
+
+        assert_eq!(
+            names(&result),
+            vec![
+                "test_unmarked_method",
+                "test_function_level",
+                "test_unmarked_in_class"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_class_level_markers() {
+        let content = r#"
+import pytest
+
+@pytest.mark.unit
+class TestMarkedClass:
+    def test_method_in_marked_class(self):
+        pass
+
+    @pytest.mark.integration
+    def test_method_with_own_marker(self):
+        pass
+
+class TestUnmarkedClass:
+    def test_method_in_unmarked_class(self):
+        pass
+
+def test_function_level():
+    pass
+"#;
+
+        let exclude_markers: HashSet<String> = ["unit", "integration"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let result = find_python_test_functions(content, &exclude_markers);
+
+        assert_eq!(
+            names(&result),
+            vec!["test_method_in_unmarked_class", "test_function_level"]
+        );
+    }
+
+    #[test]
+    fn test_line_numbers_point_at_def() {
+        let content = r#"import pytest
+
+def test_first():
+    pass
+
+@pytest.mark.parametrize(
+    "x",
+    [1, 2],
+)
+def test_decorated(x):
+    pass
+"#;
+
+        let exclude_markers: HashSet<String> = ["unit"].iter().map(|s| s.to_string()).collect();
+        let result = find_python_test_functions(content, &exclude_markers);
+
+        let lines: Vec<usize> = result.iter().map(|t| t.line).collect();
+        assert_eq!(lines, vec![3, 10]);
+    }
+
+    #[test]
+    fn test_module_level_pytestmark() {
+        let content = r#"
+import pytest
+
+pytestmark = pytest.mark.slow
+
+def test_slow_by_module():
+    pass
+"#;
+
+        let exclude_markers: HashSet<String> = ["slow"].iter().map(|s| s.to_string()).collect();
+        assert!(find_python_test_functions(content, &exclude_markers).is_empty());
+
+        let content = r#"
+import pytest
+
+def test_defined_before_pytestmark():
+    pass
+
+pytestmark = [
+    pytest.mark.integration,
+    pytest.mark.slow,
+]
+"#;
+
+        let exclude_markers: HashSet<String> =
+            ["integration"].iter().map(|s| s.to_string()).collect();
+        assert!(find_python_test_functions(content, &exclude_markers).is_empty());
+    }
+
+    #[test]
+    fn test_class_level_pytestmark() {
+        let content = r#"
+import pytest
+
+class TestMarked:
+    def test_method_before_pytestmark(self):
+        pass
+
+    pytestmark = [pytest.mark.unit]
+
+    def test_method_after_pytestmark(self):
+        pass
+
+def test_function_level():
+    pass
+"#;
+
+        let exclude_markers: HashSet<String> = ["unit"].iter().map(|s| s.to_string()).collect();
+        let result = find_python_test_functions(content, &exclude_markers);
+
+        assert_eq!(names(&result), vec!["test_function_level"]);
+    }
+
+    #[test]
+    fn test_pytestmark_scopes() {
+        let content = r#"
+import pytest
+
+pytestmark = pytest.mark.integration
+
+class TestOuter:
+    pytestmark = pytest.mark.slow
+
+    def test_outer(self):
+        pytestmark = pytest.mark.local_variable
+
+    class TestInner:
+        pytestmark = [pytest.mark.unit]
+
+        def test_inner(self):
+            pass
+
+    def test_outer_after_inner(self):
+        pass
+
+class TestSibling:
+    def test_sibling(self):
+        pass
+
+def test_module_level():
+    pass
+"#;
+
+        let result = collect_python_tests(content, &ParseOptions::default());
+        let scopes: Vec<(&str, Vec<&str>, Vec<&str>)> = result
+            .iter()
+            .map(|test| {
+                (
+                    test.name.as_str(),
+                    test.marker_sources
+                        .module
+                        .iter()
+                        .map(String::as_str)
+                        .collect(),
+                    test.marker_sources
+                        .class
+                        .iter()
+                        .map(String::as_str)
+                        .collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            scopes,
+            vec![
+                ("test_outer", vec!["integration"], vec!["slow"]),
+                ("test_inner", vec!["integration"], vec!["slow", "unit"]),
+                ("test_outer_after_inner", vec!["integration"], vec!["slow"]),
+                ("test_sibling", vec!["integration"], vec![]),
+                ("test_module_level", vec!["integration"], vec![]),
+            ]
+        );
+        assert_parsers_agree(content, &ParseOptions::default());
+    }
+
+    #[test]
+    fn test_decorators_with_inline_comments() {
+        let content = r#"
+@pytest.mark.unit  # categorized (see docs)
+@pytest.mark.parametrize(  # stray ) in a comment
+    "x",
+    [1, 2],  # ( unbalanced
+)
+@pytest.mark.slow
+def test_commented_decorators(x):
+    pass
+
+def test_unmarked():
+    pass
+"#;
+
+        let result = collect_python_tests(content, &ParseOptions::default());
+        assert_eq!(
+            names(&result),
+            vec!["test_commented_decorators", "test_unmarked"]
+        );
+        assert_eq!(
+            result[0].markers,
+            BTreeSet::from(["slow".to_string(), "unit".to_string()])
+        );
+        assert_eq!(result[0].cases, Some(2));
+        assert!(result[1].markers.is_empty());
+        assert_parsers_agree(content, &ParseOptions::default());
+    }
+
+    #[test]
+    fn test_mark_aliases() {
+        let content = r#"
+import pytest
+
+custom = pytest.mark.unit
+slow_db = mark.slow(reason="db")
+
+@custom
+def test_aliased():
+    pass
+
+@slow_db
+def test_aliased_call():
+    pass
+
+custom = object()
+
+@custom
+def test_rebound():
+    pass
+
+def test_unmarked():
+    pass
+"#;
+
+        let exclude_markers = HashSet::from(["unit".to_string(), "slow".to_string()]);
+        let result = find_python_test_functions(content, &exclude_markers);
+        assert_eq!(names(&result), vec!["test_rebound", "test_unmarked"]);
+
+        let result = collect_python_tests(content, &ParseOptions::default());
+        assert_eq!(result[0].markers, BTreeSet::from(["unit".to_string()]));
+        assert_eq!(result[1].markers, BTreeSet::from(["slow".to_string()]));
+        assert_eq!(result[2].markers, BTreeSet::from(["custom".to_string()]));
+        assert_parsers_agree(content, &ParseOptions::default());
+
+        // Aliases of marks still count with strict marks, other bare decorators don't
+        let options = ParseOptions {
+            strict_marks: true,
+            ..ParseOptions::default()
+        };
+        let result = collect_python_tests(content, &options);
+        assert_eq!(result[0].markers, BTreeSet::from(["unit".to_string()]));
+        assert!(result[2].markers.is_empty());
+        assert_parsers_agree(content, &options);
+    }
+
+    #[test]
+    fn test_ignores_strings_and_comments() {
+        let content = r#"
+def helper():
+    """Example usage:
+
+def test_in_docstring():
+    pass
+"""
+    example = "def test_in_string():"
+
+# def test_commented_out():
+#     pass
+
+def test_real():  # def test_in_trailing_comment():
+    pass
+"#;
+
+        let exclude_markers: HashSet<String> = ["unit"].iter().map(|s| s.to_string()).collect();
+        let result = find_python_test_functions(content, &exclude_markers);
+
+        assert_eq!(names(&result), vec!["test_real"]);
+        assert_eq!(result[0].line, 13);
+    }
+
+    #[test]
+    fn test_mask_strings_and_comments() {
+        assert_eq!(
+            mask_strings_and_comments("x = 'a#b'  # comment\ny = \"\\\"(\""),
+            "x = '   '  \ny = \"   \""
+        );
+        assert_eq!(
+            mask_strings_and_comments("'''\ndef test_x():\n'''"),
+            "'''\n             \n'''"
+        );
+    }
+
+    #[test]
+    fn test_backslash_continued_decorator() {
+        let content = r#"
+import pytest
+
+@pytest.mark.unit
+@pytest.mark.parametrize("x", \
+    [1, 2])
+def test_continued_arguments(x):
+    pass
+
+@pytest.mark.unit
+@pytest.mark.parametrize \
+    ("x", [1, 2])
+def test_continued_call(x):
+    pass
+
+@pytest.mark.parametrize("x", \
+    [1, 2])
+def test_unmarked(x):
+    pass
+"#;
+
+        let exclude_markers: HashSet<String> = ["unit"].iter().map(|s| s.to_string()).collect();
+        let result = find_python_test_functions(content, &exclude_markers);
+
+        assert_eq!(names(&result), vec!["test_unmarked"]);
+    }
+
+    #[test]
+    fn test_testcase_classes() {
+        let content = r#"
+import unittest
+
+class Helpers:
+    def test_helper_method(self):
+        pass
+
+@pytest.mark.unit
+class FooTests(unittest.TestCase):
+    def test_in_testcase(self):
+        pass
+
+@pytest.mark.unit
+class TestCollected:
+    def test_in_test_class(self):
+        pass
+
+def test_function_level():
+    pass
+"#;
+
+        let exclude_markers: HashSet<String> = ["unit"].iter().map(|s| s.to_string()).collect();
+
+        // Methods of all classes are collected, but only markers of test classes apply
+        let result = find_python_test_functions(content, &exclude_markers);
+        assert_eq!(
+            names(&result),
+            vec![
+                "test_helper_method",
+                "test_in_testcase",
+                "test_function_level"
+            ]
+        );
+
+        let options = ParseOptions {
+            include_testcase: true,
+            ..ParseOptions::default()
+        };
+        let result = find_python_test_functions_with_options(content, &exclude_markers, &options);
+        assert_eq!(
+            names(&result),
+            vec!["test_helper_method", "test_function_level"]
+        );
+        assert_parsers_agree(content, &options);
+    }
+
+    #[test]
+    fn test_custom_test_prefixes() {
+        let content = r#"
+def it_works():
+    pass
+
+def should_work():
+    pass
+
+def test_default_prefix():
+    pass
+
+def txsomething():
+    pass
+
+def t_literal():
+    pass
+"#;
+
+        let exclude_markers: HashSet<String> = ["unit"].iter().map(|s| s.to_string()).collect();
+        let options = ParseOptions {
+            test_prefixes: vec!["it_".to_string(), "should_".to_string(), "t.".to_string()],
+            ..ParseOptions::default()
+        };
+        let result = find_python_test_functions_with_options(content, &exclude_markers, &options);
+
+        assert_eq!(names(&result), vec!["it_works", "should_work"]);
+    }
+
+    #[test]
+    fn test_custom_class_prefixes() {
+        let content = r#"
+@pytest.mark.unit
+class Helpers:
+    def test_helper(self):
+        pass
+
+    class TestNested:
+        def test_nested(self):
+            pass
+
+@pytest.mark.integration
+class DescribeParser:
+    def test_parse(self):
+        pass
+
+class TestDefault:
+    def test_default(self):
+        pass
+"#;
+
+        let result = collect_python_tests(content, &ParseOptions::default());
+        assert_eq!(
+            names(&result),
+            vec!["test_helper", "test_nested", "test_parse", "test_default"]
+        );
+        assert!(result.iter().all(|test| test.markers.is_empty()));
+        assert_parsers_agree(content, &ParseOptions::default());
+
+        let options = ParseOptions {
+            class_prefixes: vec!["Describe".to_string(), "Helpers".to_string()],
+            ..ParseOptions::default()
+        };
+        let result = collect_python_tests(content, &options);
+        assert_eq!(result[0].markers, BTreeSet::from(["unit".to_string()]));
+        assert_eq!(result[1].markers, BTreeSet::from(["unit".to_string()]));
+        assert_eq!(
+            result[2].markers,
+            BTreeSet::from(["integration".to_string()])
+        );
+        assert_parsers_agree(content, &options);
+    }
+
+    #[test]
+    fn test_unicode_identifiers() {
+        let content = r#"
+pytestmark = pytest.mark.größe
+
+def test_café():
+    label = "naïve"
+
+class TestÜber:
+    @pytest.mark.überprüft
+    def test_日本語(self):
+        pass
+
+	# A tab-indented comment with non-ASCII text: ✓
+    def test_ñ(self):
+        x = "日本"  # ✓ still in the class
+"#;
+
+        let result = collect_python_tests(content, &ParseOptions::default());
+        assert_eq!(names(&result), vec!["test_café", "test_日本語", "test_ñ"]);
+        assert_eq!(result[1].class_path, vec!["TestÜber".to_string()]);
+        assert_eq!(
+            result[1].markers,
+            BTreeSet::from(["größe".to_string(), "überprüft".to_string()])
+        );
+        assert_eq!(result[2].class_path, vec!["TestÜber".to_string()]);
+        assert_parsers_agree(content, &ParseOptions::default());
+    }
+
+    #[test]
+    fn test_markers_inherited_from_base_classes() {
+        let content = r#"
+class BaseMarked:
+    pytestmark = pytest.mark.integration
+
+@pytest.mark.slow
+class TestA(BaseMarked):
+    def test_a(self):
+        pass
+
+class TestB(TestA, metaclass=Meta):
+    def test_b(self):
+        pass
+
+class TestC(other.BaseMarked, Unknown):
+    def test_c(self):
+        pass
+"#;
+
+        let result = collect_python_tests(content, &ParseOptions::default());
+        assert_eq!(names(&result), vec!["test_a", "test_b", "test_c"]);
+        let expected = BTreeSet::from(["integration".to_string(), "slow".to_string()]);
+        assert_eq!(result[0].marker_sources.class, expected);
+        assert_eq!(result[1].marker_sources.class, expected);
+        assert!(result[2].markers.is_empty());
+        assert_parsers_agree(content, &ParseOptions::default());
+    }
+
+    #[test]
+    fn test_detect_body_skips() {
+        let content = r#"
+def test_skipped():
+    """Not ready yet.
+
+    Still not ready.
+    """
+    pytest.skip("not ready")
+
+def test_skipped_multiline_signature(
+    a,
+    b,
+):
+    pytest.skip(
+        "not ready"
+    )
+
+def test_conditional():
+    if sys.platform == "win32":
+        pytest.skip("unix only")
+    pass
+
+def test_late():
+    x = 1
+    y = 2
+    z = 3
+    pytest.skip("too late")
+
+class TestFoo:
+    def test_method(self):
+        pytest.skip("not ready")
+
+def test_one_line(): pytest.skip("not ready")
+
+def test_one_line_annotated(a: dict[str, int] = {"a": 1}) -> None: pytest.skip("x")
+
+def test_after():
+    pass
+"#;
+
+        let skipped = |result: &[TestFunction]| -> Vec<String> {
+            result
+                .iter()
+                .filter(|test| test.markers.contains("skip"))
+                .map(|test| test.name.clone())
+                .collect()
+        };
+
+        let result = collect_python_tests(content, &ParseOptions::default());
+        assert!(skipped(&result).is_empty());
+
+        let options = ParseOptions {
+            detect_body_skips: true,
+            ..ParseOptions::default()
+        };
+        let result = collect_python_tests(content, &options);
+        assert_eq!(
+            skipped(&result),
+            vec![
+                "test_skipped",
+                "test_skipped_multiline_signature",
+                "test_method",
+                "test_one_line",
+                "test_one_line_annotated"
+            ]
+        );
+        assert_parsers_agree(content, &options);
+    }
+
+    #[test]
+    fn test_async_test_functions() {
+        let content = r#"
+import pytest
+
+async def test_async_unmarked():
+    pass
+
+@pytest.mark.unit
+async def test_async_marked():
+    pass
+"#;
+
+        let exclude_markers: HashSet<String> = ["unit"].iter().map(|s| s.to_string()).collect();
+        let result = find_python_test_functions(content, &exclude_markers);
+
+        assert_eq!(names(&result), vec!["test_async_unmarked"]);
+        assert_eq!(result[0].line, 4);
+    }
+
+    #[test]
+    fn test_ignored_marker_names() {
+        let content = r#"
+import pytest
+
+pytestmark = pytest.mark.filterwarnings("ignore")
+
+@pytest.mark.parametrize("x", [1, 2])
+def test_parametrized_only(x):
+    pass
+
+@pytest.mark.usefixtures("db")
+class TestWithFixtures:
+    def test_method(self):
+        pass
+"#;
+
+        let exclude_markers: HashSet<String> = ["parametrize", "usefixtures", "filterwarnings"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        let result = find_python_test_functions(content, &exclude_markers);
+        assert_eq!(
+            names(&result),
+            vec!["test_parametrized_only", "test_method"]
+        );
+
+        let options = ParseOptions {
+            ignored_markers: HashSet::new(),
+            ..ParseOptions::default()
+        };
+        let result = find_python_test_functions_with_options(content, &exclude_markers, &options);
+        assert!(result.is_empty());
+    }
+
+    #[test]
+    fn test_functions_nested_in_functions_are_not_collected() {
+        let content = r#"
+def make_tests():
+    def test_generated():
+        pass
+
+    class TestGenerated:
+        def test_method(self):
+            pass
+
+    return test_generated
+
+class TestOuter:
+    def test_method(self):
+        @pytest.mark.unit
+        def test_helper():
+            pass
+
+    async def test_async(self):
+        def test_inner():
+            pass
+
+def test_after():
+    pass
+"#;
+
+        let result = collect_python_tests(content, &ParseOptions::default());
+        assert_eq!(
+            names(&result),
+            vec!["test_method", "test_async", "test_after"]
+        );
+        assert!(result.iter().all(|test| test.markers.is_empty()));
+        assert_parsers_agree(content, &ParseOptions::default());
+    }
+
+    #[test]
+    fn test_nested_class_markers() {
+        let content = r#"
+import pytest
+
+@pytest.mark.unit
+class TestOuter:
+    class TestInner:
+        def test_inherits_outer_marker(self):
+            pass
+
+    def test_in_outer(self):
+        pass
+
+class TestPlain:
+    @pytest.mark.integration
+    class TestMarkedInner:
+        class TestInnermost:
+            def test_inherits_inner_marker(self):
+                pass
+
+    def test_after_inner_class(self):
+        pass
+
+def test_function_level():
+    pass
+"#;
+
+        let exclude_markers: HashSet<String> = ["unit", "integration"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let result = find_python_test_functions(content, &exclude_markers);
+
+        assert_eq!(
+            names(&result),
+            vec!["test_after_inner_class", "test_function_level"]
+        );
+    }
+
+    #[test]
+    fn test_crlf_line_endings() {
+        let content = "import pytest\r\n\r\n\
+                       @pytest.mark.unit\r\n\
+                       class TestMarked:\r\n\
+                       \x20   def test_in_marked_class(self):\r\n\
+                       \x20       pass\r\n\r\n\
+                       def test_after_class():\r\n\
+                       \x20   pass\r\n";
+
+        let exclude_markers: HashSet<String> = ["unit"].iter().map(|s| s.to_string()).collect();
+        let result = find_python_test_functions(content, &exclude_markers);
+
+        assert_eq!(names(&result), vec!["test_after_class"]);
+        assert_eq!(result[0].line, 8);
+    }
+
+    #[test]
+    fn test_collect_python_tests_markers() {
+        let content = r#"
+import pytest
+
+pytestmark = pytest.mark.slow
+
+@pytest.mark.integration
+class TestClass:
+    @pytest.mark.unit
+    @pytest.mark.parametrize("x", [1])
+    def test_method(self, x):
+        pass
+
+def test_function():
+    pass
+"#;
+
+        let result = collect_python_tests(content, &ParseOptions::default());
+
+        let markers: Vec<Vec<&str>> = result
+            .iter()
+            .map(|t| t.markers.iter().map(String::as_str).collect())
+            .collect();
+        assert_eq!(names(&result), vec!["test_method", "test_function"]);
+        assert_eq!(
+            markers,
+            vec![vec!["integration", "slow", "unit"], vec!["slow"]]
+        );
+
+        let sources = &result[0].marker_sources;
+        assert_eq!(sources.module, BTreeSet::from(["slow".to_string()]));
+        assert_eq!(sources.class, BTreeSet::from(["integration".to_string()]));
+        assert_eq!(sources.function, BTreeSet::from(["unit".to_string()]));
+    }
+
+    #[test]
+    fn test_comments_between_decorators() {
+        let content = r#"
+import pytest
+
+@pytest.mark.unit
+# TODO: split into smaller tests
+
+def test_x():
+    pass
+
+@pytest.mark.unit
+    # indented comment
+@pytest.mark.parametrize("x", [1])
+def test_y(x):
+    pass
+
+def test_unmarked():
+    pass
+"#;
+
+        let exclude_markers: HashSet<String> = ["unit"].iter().map(|s| s.to_string()).collect();
+        let result = find_python_test_functions(content, &exclude_markers);
+
+        assert_eq!(names(&result), vec!["test_unmarked"]);
+    }
+
+    #[test]
+    fn test_tab_indentation() {
+        let exclude_markers: HashSet<String> = ["unit"].iter().map(|s| s.to_string()).collect();
+
+        let content = "import pytest\n\n\
+                       @pytest.mark.unit\n\
+                       class TestMarked:\n\
+                       \tdef test_in_marked_class(self):\n\
+                       \t\tpass\n\n\
+                       class TestTabs:\n\
+                       \t@pytest.mark.unit\n\
+                       \tdef test_marked(self):\n\
+                       \t\tpass\n\n\
+                       \tdef test_unmarked(self):\n\
+                       \t\tpass\n";
+        let result = find_python_test_functions(content, &exclude_markers);
+        assert_eq!(names(&result), vec!["test_unmarked"]);
+
+        // A tab reaches column 8, deeper than the 4 spaces of the nested class
+        let content = "import pytest\n\n\
+                       class TestOuter:\n\
+                       \x20   @pytest.mark.unit\n\
+                       \x20   class TestInner:\n\
+                       \tdef test_in_inner(self):\n\
+                       \t    pass\n";
+        let result = find_python_test_functions(content, &exclude_markers);
+        assert!(result.is_empty());
+
+        let options = ParseOptions {
+            tab_width: 2,
+            ..ParseOptions::default()
+        };
+        let result = find_python_test_functions_with_options(content, &exclude_markers, &options);
+        assert_eq!(names(&result), vec!["test_in_inner"]);
+    }
+
+    #[test]
+    fn test_marker_aliases() {
+        let content = r#"
+import sys
+import pytest
+
+@pytest.mark.skipif(sys.platform == "win32", reason="posix only")
+def test_skipif():
+    pass
+
+@pytest.mark.xfail
+def test_xfail():
+    pass
+
+def test_unmarked():
+    pass
+"#;
+
+        let exclude_markers: HashSet<String> = ["skip"].iter().map(|s| s.to_string()).collect();
+        let result = find_python_test_functions(content, &exclude_markers);
+        assert_eq!(
+            names(&result),
+            vec!["test_skipif", "test_xfail", "test_unmarked"]
+        );
+
+        let options = ParseOptions {
+            marker_aliases: [("skipif", "skip"), ("xfail", "skip")]
+                .iter()
+                .map(|(from, to)| (from.to_string(), to.to_string()))
+                .collect(),
+            ..ParseOptions::default()
+        };
+        let result = find_python_test_functions_with_options(content, &exclude_markers, &options);
+        assert_eq!(names(&result), vec!["test_unmarked"]);
+    }
+
+    #[test]
+    fn test_class_path() {
+        let content = r#"
+class TestOuter:
+    def test_outer(self):
+        pass
+
+    class TestInner:
+        def test_inner(self):
+            pass
+
+def test_function():
+    pass
+"#;
+
+        let result = find_python_test_functions(content, &HashSet::new());
+
+        let class_paths: Vec<Vec<&str>> = result
+            .iter()
+            .map(|t| t.class_path.iter().map(String::as_str).collect())
+            .collect();
+        assert_eq!(
+            class_paths,
+            vec![vec!["TestOuter"], vec!["TestOuter", "TestInner"], vec![]]
+        );
+    }
+
+    #[test]
+    fn test_fixtures_are_not_collected() {
+        let content = r#"
+import pytest
+from pytest import fixture
+
+@pytest.fixture
+def test_db():
+    pass
+
+@pytest.fixture(scope="module")
+def test_client():
+    pass
+
+@fixture(
+    autouse=True,
+)
+def test_settings():
+    pass
+
+class TestWithFixture:
+    @pytest.fixture
+    def test_data(self):
+        pass
+
+    def test_method(self, test_data):
+        pass
+
+def test_unmarked(test_db):
+    pass
+"#;
+
+        let result = find_python_test_functions(content, &HashSet::new());
+
+        assert_eq!(names(&result), vec!["test_method", "test_unmarked"]);
+    }
+
+    #[test]
+    fn test_multiline_signature_between_decorated_tests() {
+        let content = r#"
+import pytest
+
+@pytest.mark.slow
+def test_first(
+    a,
+    b,
+):
+    pass
+
+@pytest.mark.unit
+def test_second():
+    pass
+
+@pytest.mark.slow
+def test_body_on_closing_line(
+    a,
+    b=(1, 2),
+) -> None: assert f(a,
+    b)
+@pytest.mark.unit
+def test_after_unbalanced_body():
+    pass
+
+def test_unmarked(
+    a,
+):
+    pass
+"#;
+
+        let result = collect_python_tests(content, &ParseOptions::default());
+        let markers: Vec<(&str, Vec<&str>)> = result
+            .iter()
+            .map(|test| {
+                (
+                    test.name.as_str(),
+                    test.markers.iter().map(String::as_str).collect(),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            markers,
+            vec![
+                ("test_first", vec!["slow"]),
+                ("test_second", vec!["unit"]),
+                ("test_body_on_closing_line", vec!["slow"]),
+                ("test_after_unbalanced_body", vec!["unit"]),
+                ("test_unmarked", vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_brackets_inside_decorator_strings() {
+        let content = r#"
+import pytest
+
+@pytest.mark.unit
+@pytest.mark.parametrize(
+    "a(b",
+    ["[", "{x", ')'],
+)
+def test_unbalanced_in_strings(a):
+    pass
+
+@pytest.mark.parametrize("a(b", [1])
+@pytest.mark.slow
+def test_single_line(a):
+    pass
+
+@pytest.mark.parametrize("x", [")"])
+def test_unmarked(x):
+    pass
+"#;
+
+        let result = collect_python_tests(content, &ParseOptions::default());
+        let markers: Vec<(&str, Vec<&str>)> = result
+            .iter()
+            .map(|test| {
+                (
+                    test.name.as_str(),
+                    test.markers.iter().map(String::as_str).collect(),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            markers,
+            vec![
+                ("test_unbalanced_in_strings", vec!["unit"]),
+                ("test_single_line", vec!["slow"]),
+                ("test_unmarked", vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_skip_non_instance_methods() {
+        let content = r#"
+class TestHelpers:
+    @staticmethod
+    def test_static():
+        pass
+
+    @classmethod
+    def test_class(cls):
+        pass
+
+    def test_instance(self):
+        pass
+
+@staticmethod
+def test_module_level():
+    pass
+"#;
+
+        let result = find_python_test_functions(content, &HashSet::new());
+        assert_eq!(
+            names(&result),
+            vec![
+                "test_static",
+                "test_class",
+                "test_instance",
+                "test_module_level"
+            ]
+        );
+
+        let options = ParseOptions {
+            skip_non_instance_methods: true,
+            ..ParseOptions::default()
+        };
+        let result = collect_python_tests(content, &options);
+        assert_eq!(names(&result), vec!["test_instance", "test_module_level"]);
+        assert_parsers_agree(content, &options);
+    }
+
+    #[test]
+    fn test_parametrize_case_markers() {
+        let markers = |decorator: &str| -> BTreeSet<String> {
+            parametrize_case_markers(&mask_strings_and_comments(decorator))
+                .into_iter()
+                .collect()
+        };
+
+        assert_eq!(
+            markers(
+                r#"@pytest.mark.parametrize("x", [pytest.param(1, marks=pytest.mark.slow), pytest.param(2, marks=[pytest.mark.slow, pytest.mark.unit], id="b(")])"#
+            ),
+            BTreeSet::from(["slow".to_string()])
+        );
+        assert_eq!(
+            markers(
+                r#"@pytest.mark.parametrize(argnames="x", argvalues=(param(1, marks=mark.slow),))"#
+            ),
+            BTreeSet::from(["slow".to_string()])
+        );
+        // A plain case runs without the marker
+        assert!(
+            markers(
+                r#"@pytest.mark.parametrize("x", [pytest.param(1, marks=pytest.mark.slow), 2])"#
+            )
+            .is_empty()
+        );
+        assert!(markers(r#"@pytest.mark.parametrize("x", CASES)"#).is_empty());
+        assert!(markers("@pytest.mark.unit").is_empty());
+    }
+
+    #[test]
+    fn test_decorator_on_first_line() {
+        let exclude_markers = HashSet::from(["unit".to_string()]);
+        for content in [
+            "@pytest.mark.unit\ndef test_a():\n    pass\n",
+            "\n\n@pytest.mark.unit\ndef test_a():\n    pass\n",
+        ] {
+            let result = find_python_test_functions(content, &exclude_markers);
+            assert!(result.is_empty(), "{:?}", content);
+            assert_eq!(
+                collect_python_tests(content, &ParseOptions::default())[0].markers,
+                BTreeSet::from(["unit".to_string()])
+            );
+        }
+    }
+
+    #[test]
+    fn test_suppression_comment() {
+        let content = r#"
+import pytest
+
+# collect-unmarked: ignore
+def test_suppressed():
+    pass
+
+# Flaky on CI
+#  collect-unmarked: ignore
+@pytest.mark.parametrize("x", [1, 2])
+def test_suppressed_with_decorator(x):
+    pass
+
+# collect-unmarked: ignore
+x = 1
+
+def test_after_statement():
+    pass
+
+class TestFoo:
+    @pytest.mark.parametrize("x", [1, 2])
+    # collect-unmarked: ignore
+    def test_suppressed_method(self, x):
+        pass
+
+    def test_method(self):
+        s = """
+        # collect-unmarked: ignore
+        """
+
+    def test_after_string(self):
+        pass
+"#;
+
+        let exclude_markers = HashSet::new();
+        let result = find_python_test_functions(content, &exclude_markers);
+        assert_eq!(
+            names(&result),
+            vec!["test_after_statement", "test_method", "test_after_string"]
+        );
+        assert_parsers_agree(content, &ParseOptions::default());
+    }
+
+    #[test]
+    fn test_parametrize_cases() {
+        let cases = |decorators: &[&str]| {
+            let decorators: Vec<String> = decorators
+                .iter()
+                .map(|decorator| mask_strings_and_comments(decorator))
+                .collect();
+            parametrize_cases(decorators.iter().map(String::as_str))
+        };
+
+        assert_eq!(
+            cases(&[r#"@pytest.mark.parametrize("x", [1, 2, 3])"#]),
+            Some(3)
+        );
+        assert_eq!(
+            cases(&[r#"@pytest.mark.parametrize("x, y", [(1, 2), (3, 4),])"#]),
+            Some(2)
+        );
+        assert_eq!(
+            cases(&[r#"@pytest.mark.parametrize(argvalues=("a,b", "c"), argnames="x")"#]),
+            Some(2)
+        );
+        // Stacked decorators multiply
+        assert_eq!(
+            cases(&[
+                "@pytest.mark.unit",
+                r#"@pytest.mark.parametrize("x", [1, 2])"#,
+                r#"@pytest.mark.parametrize("y", [1, 2, 3])"#,
+            ]),
+            Some(6)
+        );
+        // Computed cases can't be counted
+        assert_eq!(cases(&[r#"@pytest.mark.parametrize("x", CASES)"#]), None);
+        assert_eq!(cases(&[r#"@pytest.mark.parametrize("x", range(3))"#]), None);
+        assert_eq!(
+            cases(&[r#"@pytest.mark.parametrize("x", [1, 2] + EXTRA)"#]),
+            None
+        );
+        assert_eq!(
+            cases(&[
+                r#"@pytest.mark.parametrize("x", [1, 2])"#,
+                r#"@pytest.mark.parametrize("y", CASES)"#,
+            ]),
+            None
+        );
+        assert_eq!(cases(&["@pytest.mark.unit"]), None);
+    }
+
+    #[test]
+    fn test_parametrize_cases_of_tests() {
+        let content = r#"
+import pytest
+
+@pytest.mark.parametrize(
+    "value",
+    [
+        1,
+        pytest.param(2, marks=pytest.mark.slow),
+    ],
+)
+@pytest.mark.parametrize("flag", [True, False])
+def test_multiline(value, flag):
+    pass
+
+@pytest.mark.parametrize("value", load_cases())
+def test_computed(value):
+    pass
+
+def test_plain():
+    pass
+"#;
+
+        let result = collect_python_tests(content, &ParseOptions::default());
+        let cases: Vec<Option<usize>> = result.iter().map(|test| test.cases).collect();
+        assert_eq!(cases, vec![Some(4), None, None]);
+        assert_parsers_agree(content, &ParseOptions::default());
+    }
+
+    #[test]
+    fn test_param_marks() {
+        let content = r#"
+import pytest
+
+@pytest.mark.parametrize(
+    "value",
+    [
+        pytest.param(1, marks=pytest.mark.slow),
+        pytest.param(2, marks=[pytest.mark.slow, pytest.mark.unit]),
+    ],
+)
+def test_all_cases_slow(value):
+    pass
+
+@pytest.mark.parametrize("value", [pytest.param(1, marks=pytest.mark.slow), 2])
+def test_some_cases_slow(value):
+    pass
+"#;
+
+        let exclude_markers = HashSet::from(["slow".to_string()]);
+        let result = find_python_test_functions(content, &exclude_markers);
+        assert_eq!(
+            names(&result),
+            vec!["test_all_cases_slow", "test_some_cases_slow"]
+        );
+
+        let options = ParseOptions {
+            param_marks: true,
+            ..ParseOptions::default()
+        };
+        let result = find_python_test_functions_with_options(content, &exclude_markers, &options);
+        assert_eq!(names(&result), vec!["test_some_cases_slow"]);
+        assert_parsers_agree(content, &options);
+    }
+
+    #[test]
+    fn test_collect_from_reader() {
+        let content = r#"import pytest
+
+class TestFoo:
+    @pytest.mark.unit
+    def test_a(self):
+        pass
+
+    pytestmark = [
+        pytest.mark.slow,
+    ]
+
+def test_b():
+    s = """
+    def test_in_string():
+    """
+
+pytestmark = pytest.mark.component
+"#;
+        // Files saved on Windows may start with a byte order mark and end lines with CRLF
+        let windows_content = format!("\u{feff}{}", content.replace('\n', "\r\n"));
+
+        let options = ParseOptions::default();
+        let result =
+            collect_python_tests_from_reader(windows_content.as_bytes(), &options).unwrap();
+        assert_eq!(names(&result), vec!["test_a", "test_b"]);
+        assert_eq!(
+            result[0].markers,
+            BTreeSet::from(["component", "slow", "unit"].map(String::from))
+        );
+        assert_eq!(result[1].markers, BTreeSet::from(["component".to_string()]));
+        assert_eq!(result, collect_python_tests(content, &options));
+    }
+}
@@ -0,0 +1,1704 @@
+//! Walking test directories and analyzing the Python files found in them.
+
+use clap::ValueEnum;
+use globset::GlobSet;
+use ignore::WalkBuilder;
+use ignore::gitignore::GitignoreBuilder;
+use rayon::prelude::*;
+use regex::Regex;
+use rustpython_parser::ParseError;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::archive::{self, Member};
+use crate::cache::Cache;
+use crate::config::ConfigError;
+use crate::{
+    MarkerSources, ParseOptions, TestFunction, ast_parser, collect_python_tests,
+    collect_python_tests_from_reader,
+};
+
+/// Parser used to find test functions and their decorators.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ParserKind {
+    /// Fast line-based scan
+    #[default]
+    Regex,
+    /// Full Python syntax tree (slower, but not fooled by strings or comments)
+    Ast,
+}
+
+/// Levels of markers that count when deciding whether a test is excluded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum MarkerSource {
+    /// Only the test's own decorators
+    Function,
+    /// Only the decorators and `pytestmark` of enclosing classes
+    Class,
+    /// Only the module-level `pytestmark`
+    Module,
+    /// Markers of all levels
+    #[default]
+    All,
+}
+
+impl MarkerSource {
+    /// The markers of `sources` at this level.
+    fn markers(self, sources: &MarkerSources) -> Box<dyn Iterator<Item = &String> + '_> {
+        match self {
+            MarkerSource::Function => Box::new(sources.function.iter()),
+            MarkerSource::Class => Box::new(sources.class.iter()),
+            MarkerSource::Module => Box::new(sources.module.iter()),
+            MarkerSource::All => Box::new(
+                sources
+                    .module
+                    .iter()
+                    .chain(&sources.class)
+                    .chain(&sources.function),
+            ),
+        }
+    }
+}
+
+/// A test found by a [`Scanner`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub file: PathBuf,
+    /// 1-based line number of the `def` line
+    pub line: usize,
+    /// Names of the enclosing test classes, outermost first
+    pub class_path: Vec<String>,
+    pub function: String,
+    /// Markers the test carries; none of them is excluded for tests in [`ScanReport::findings`]
+    pub markers: BTreeSet<String>,
+    /// The same markers, by the scope that applies them
+    pub marker_sources: MarkerSources,
+    /// Number of cases a parametrized test expands into, if it can be counted statically
+    pub cases: Option<usize>,
+    /// Number of markers counted towards [`Scanner::min_markers`], if a minimum is required
+    pub marker_count: Option<usize>,
+}
+
+impl Finding {
+    /// The test's name within its file as in pytest node IDs, e.g. `TestFoo::test_method`.
+    pub fn qualified_name(&self) -> String {
+        let mut parts: Vec<&str> = self.class_path.iter().map(String::as_str).collect();
+        parts.push(&self.function);
+        parts.join("::")
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}::{}",
+            self.file.display(),
+            self.line,
+            self.qualified_name()
+        )
+    }
+}
+
+/// Why a file or directory could not be scanned.
+#[derive(Debug)]
+pub enum ScanError {
+    /// A file couldn't be read, e.g. for lack of permissions or because it isn't UTF-8
+    Io(PathBuf, io::Error),
+    /// A file isn't valid Python (only detected by the AST parser)
+    Parse(PathBuf, ParseError),
+    /// A directory couldn't be walked, e.g. because of a symlink loop
+    Walk(PathBuf, String),
+    /// The configuration couldn't be loaded
+    Config(ConfigError),
+}
+
+impl ScanError {
+    /// The path that couldn't be scanned, or the configuration file that couldn't be loaded.
+    pub fn path(&self) -> &Path {
+        match self {
+            ScanError::Io(path, _) | ScanError::Parse(path, _) | ScanError::Walk(path, _) => path,
+            ScanError::Config(ConfigError::Io(path, _) | ConfigError::Parse(path, _)) => path,
+        }
+    }
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScanError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            ScanError::Parse(path, err) => {
+                write!(f, "{}: failed to parse: {}", path.display(), err)
+            }
+            ScanError::Walk(path, err) => write!(f, "{}: {}", path.display(), err),
+            ScanError::Config(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ScanError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ScanError::Io(_, err) => Some(err),
+            ScanError::Parse(_, err) => Some(err),
+            ScanError::Walk(..) => None,
+            ScanError::Config(err) => Some(err),
+        }
+    }
+}
+
+impl From<ConfigError> for ScanError {
+    fn from(err: ConfigError) -> Self {
+        ScanError::Config(err)
+    }
+}
+
+/// How many of the scanned tests carry each marker.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MarkerStats {
+    /// Number of tests found, marked or not
+    pub tests: usize,
+    /// Number of tests that carry no marker at all
+    pub without_markers: usize,
+    /// Number of tests carrying each marker
+    pub markers: BTreeMap<String, usize>,
+}
+
+impl MarkerStats {
+    fn from_tests(tests: &[Finding]) -> Self {
+        let mut stats = MarkerStats {
+            tests: tests.len(),
+            ..MarkerStats::default()
+        };
+        for test in tests {
+            if test.markers.is_empty() {
+                stats.without_markers += 1;
+            }
+            for marker in &test.markers {
+                *stats.markers.entry(marker.clone()).or_default() += 1;
+            }
+        }
+        stats
+    }
+}
+
+/// Result of a scan: the unmarked tests and the files that could not be scanned.
+#[derive(Debug, Default)]
+pub struct ScanReport {
+    /// Tests that carry none of the excluded markers
+    pub findings: Vec<Finding>,
+    /// All tests found, including those that carry an excluded marker
+    pub tests: Vec<Finding>,
+    /// Files and directories that could not be scanned
+    pub skipped: Vec<ScanError>,
+    /// Markers of all tests found, including those that carry an excluded marker
+    pub stats: MarkerStats,
+}
+
+impl ScanReport {
+    /// The findings, or the first file or directory that could not be scanned, for callers
+    /// that treat any such file as an error.
+    pub fn into_result(self) -> Result<Vec<Finding>, ScanError> {
+        match self.skipped.into_iter().next() {
+            Some(err) => Err(err),
+            None => Ok(self.findings),
+        }
+    }
+}
+
+/// Called with the path of each file once it has been analyzed, possibly from several
+/// threads at once.
+#[derive(Clone)]
+struct ProgressCallback(Arc<dyn Fn(&Path) + Send + Sync>);
+
+impl fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressCallback")
+    }
+}
+
+/// Name of the files listing, in `.gitignore` syntax, paths to skip within their directory.
+const COLLECT_IGNORE_FILE_NAME: &str = ".collectignore";
+
+/// Size in bytes above which the regex parser streams files instead of reading them at once.
+pub const DEFAULT_STREAM_THRESHOLD: u64 = 4 * 1024 * 1024;
+
+/// Scans directories for tests that carry none of the excluded markers.
+#[derive(Debug, Clone)]
+pub struct Scanner {
+    exclude_markers: HashSet<String>,
+    exclude_marker_patterns: Vec<Regex>,
+    marker_source: MarkerSource,
+    min_markers: Option<usize>,
+    fail_fast: bool,
+    directories: Vec<PathBuf>,
+    parser: ParserKind,
+    parse_options: ParseOptions,
+    respect_ignore_files: bool,
+    extensions: Vec<String>,
+    include_paths: Option<GlobSet>,
+    exclude_paths: GlobSet,
+    skip_file_names: GlobSet,
+    exclude_classes: GlobSet,
+    max_depth: Option<usize>,
+    follow_symlinks: bool,
+    on_file_scanned: Option<ProgressCallback>,
+    cache_dir: Option<PathBuf>,
+    stream_threshold: u64,
+}
+
+impl Scanner {
+    pub fn new(exclude_markers: HashSet<String>) -> Self {
+        Self {
+            exclude_markers,
+            exclude_marker_patterns: Vec::new(),
+            marker_source: MarkerSource::default(),
+            min_markers: None,
+            fail_fast: false,
+            directories: Vec::new(),
+            parser: ParserKind::default(),
+            parse_options: ParseOptions::default(),
+            respect_ignore_files: true,
+            extensions: vec!["py".to_string()],
+            include_paths: None,
+            exclude_paths: GlobSet::empty(),
+            skip_file_names: GlobSet::empty(),
+            exclude_classes: GlobSet::empty(),
+            max_depth: None,
+            follow_symlinks: false,
+            on_file_scanned: None,
+            cache_dir: None,
+            stream_threshold: DEFAULT_STREAM_THRESHOLD,
+        }
+    }
+
+    /// Also exclude tests carrying any marker that matches one of `patterns`, e.g.
+    /// `^feature_` for dynamically named markers.
+    pub fn exclude_marker_patterns(mut self, patterns: Vec<Regex>) -> Self {
+        self.exclude_marker_patterns = patterns;
+        self
+    }
+
+    /// Only count markers applied at this level when deciding whether a test is excluded
+    /// (default: all levels).
+    pub fn marker_source(mut self, marker_source: MarkerSource) -> Self {
+        self.marker_source = marker_source;
+        self
+    }
+
+    /// Report tests carrying fewer than `min_markers` distinct markers instead of those
+    /// without an excluded marker, e.g. `2` to require a type and a domain marker.
+    pub fn min_markers(mut self, min_markers: Option<usize>) -> Self {
+        self.min_markers = min_markers;
+        self
+    }
+
+    /// Stop scanning at the first file with a finding and report only its first finding
+    /// (default: false).
+    ///
+    /// Files are then analyzed one at a time in walk order, and directories are only
+    /// walked as far as needed.
+    pub fn fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    /// Add directories to scan; they are scanned in the given order.
+    pub fn directories<I, P>(mut self, directories: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PathBuf>,
+    {
+        self.directories
+            .extend(directories.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn parser(mut self, parser: ParserKind) -> Self {
+        self.parser = parser;
+        self
+    }
+
+    pub fn parse_options(mut self, parse_options: ParseOptions) -> Self {
+        self.parse_options = parse_options;
+        self
+    }
+
+    /// Skip paths excluded by .gitignore, .ignore and global git excludes (default: true).
+    pub fn respect_ignore_files(mut self, respect_ignore_files: bool) -> Self {
+        self.respect_ignore_files = respect_ignore_files;
+        self
+    }
+
+    /// Scan files with any of these extensions, given without the leading dot, e.g. `pyx`
+    /// for Cython test files (default: `py`).
+    pub fn extensions(mut self, extensions: Vec<String>) -> Self {
+        self.extensions = extensions;
+        self
+    }
+
+    /// Only scan files whose path relative to the scanned directory matches any of `globs`
+    /// (default: all files). Excluded paths are skipped even if they match.
+    pub fn include_paths(mut self, globs: GlobSet) -> Self {
+        self.include_paths = Some(globs);
+        self
+    }
+
+    /// Skip files whose path relative to the scanned directory matches any of `globs`.
+    pub fn exclude_paths(mut self, globs: GlobSet) -> Self {
+        self.exclude_paths = globs;
+        self
+    }
+
+    /// Skip files whose name matches any of `globs`, wherever they are located.
+    pub fn skip_file_names(mut self, globs: GlobSet) -> Self {
+        self.skip_file_names = globs;
+        self
+    }
+
+    /// Skip test methods of classes whose name matches any of `globs`, including classes
+    /// nested in them, e.g. abstract base classes named `Test*`.
+    pub fn exclude_classes(mut self, globs: GlobSet) -> Self {
+        self.exclude_classes = globs;
+        self
+    }
+
+    /// Limit how deep directories are descended into; depth 1 only scans the files
+    /// directly in each directory (default: unlimited).
+    pub fn max_depth(mut self, max_depth: Option<usize>) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Descend into symlinked directories (default: false).
+    ///
+    /// Symlink loops are detected and reported as skipped paths.
+    pub fn follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    /// Cache the tests found in each file in `cache_dir`, and reuse them for files whose
+    /// size and modification time are unchanged (default: no cache).
+    pub fn cache_dir(mut self, cache_dir: Option<PathBuf>) -> Self {
+        self.cache_dir = cache_dir;
+        self
+    }
+
+    /// Stream files larger than `stream_threshold` bytes line by line with the regex parser,
+    /// instead of reading them into memory at once (default: 4 MiB).
+    ///
+    /// The AST parser always reads whole files.
+    pub fn stream_threshold(mut self, stream_threshold: u64) -> Self {
+        self.stream_threshold = stream_threshold;
+        self
+    }
+
+    /// Call `callback` with the path of each file once it has been analyzed, e.g. to
+    /// report progress. It may be called from several threads at once.
+    pub fn on_file_scanned(mut self, callback: impl Fn(&Path) + Send + Sync + 'static) -> Self {
+        self.on_file_scanned = Some(ProgressCallback(Arc::new(callback)));
+        self
+    }
+
+    /// Like [`Scanner::scan`], but fail if any file or directory could not be scanned.
+    pub fn try_scan(&self) -> Result<Vec<Finding>, ScanError> {
+        self.scan().into_result()
+    }
+
+    /// Scan all directories; findings within each directory are sorted by path and line.
+    ///
+    /// A Python file may be given in place of a directory to scan just that file; path
+    /// filters then match its file name. A `.tar`, `.tar.gz`, `.zip` or `.whl` archive is scanned
+    /// member by member, with path filters matching the paths within the archive and
+    /// findings reported under `archive!member`.
+    ///
+    /// Directories are scanned in parallel, but reported in the given order. A test
+    /// reachable from several overlapping directories (e.g. `.` and `./tests`) is reported
+    /// once, under the path it was first found with.
+    pub fn scan(&self) -> ScanReport {
+        let cache = self.load_cache();
+        if self.fail_fast {
+            let (tests, skipped) = self.scan_until_finding(cache.as_ref());
+            save_cache(cache);
+            return self.report(tests, skipped);
+        }
+        let mut tests = Vec::new();
+        let mut skipped = Vec::new();
+        let mut seen = HashSet::new();
+
+        let results: Vec<_> = self
+            .directories
+            .par_iter()
+            .map(|directory| self.scan_directory(directory, cache.as_ref()))
+            .collect();
+        for (directory_tests, directory_skipped) in results {
+            tests.extend(unseen_tests(directory_tests, &mut seen));
+            skipped.extend(directory_skipped);
+        }
+
+        save_cache(cache);
+        self.report(tests, skipped)
+    }
+
+    /// List the files [`Scanner::scan`] would analyze, after all filters, without reading
+    /// them; paths that couldn't be walked are returned as skipped.
+    ///
+    /// A file reachable from several overlapping directories is listed once.
+    pub fn files(&self) -> (Vec<PathBuf>, Vec<ScanError>) {
+        let mut files = Vec::new();
+        let mut skipped = Vec::new();
+        let mut seen = HashSet::new();
+
+        let results: Vec<_> = self
+            .directories
+            .par_iter()
+            .map(|directory| self.directory_files(directory))
+            .collect();
+        for (directory_files, directory_skipped) in results {
+            files.extend(directory_files.into_iter().filter(|file| {
+                seen.insert(std::fs::canonicalize(file).unwrap_or_else(|_| file.clone()))
+            }));
+            skipped.extend(directory_skipped);
+        }
+
+        (files, skipped)
+    }
+
+    /// Scan the directories file by file until one has a finding.
+    fn scan_until_finding(&self, cache: Option<&Cache>) -> (Vec<Finding>, Vec<ScanError>) {
+        let mut tests = Vec::new();
+        let mut skipped = Vec::new();
+        let mut seen = HashSet::new();
+
+        for directory in &self.directories {
+            let (directory_tests, directory_skipped) =
+                if directory.is_file() && archive::is_archive(directory) {
+                    // Archive members are only readable in archive order, so read them at once
+                    self.scan_archive(directory)
+                } else if directory.is_file() {
+                    let (files, _) = self.directory_files(directory);
+                    self.analyze_until_finding(files.into_iter().map(Ok), cache)
+                } else {
+                    self.analyze_until_finding(self.walk(directory), cache)
+                };
+            let directory_tests = unseen_tests(directory_tests, &mut seen);
+            let found = directory_tests.iter().any(|test| self.is_finding(test));
+            tests.extend(directory_tests);
+            skipped.extend(directory_skipped);
+            if found {
+                break;
+            }
+        }
+
+        (tests, skipped)
+    }
+
+    /// Analyze `files` one at a time, stopping after the first file with a finding.
+    fn analyze_until_finding(
+        &self,
+        files: impl Iterator<Item = Result<PathBuf, ScanError>>,
+        cache: Option<&Cache>,
+    ) -> (Vec<Finding>, Vec<ScanError>) {
+        let mut tests = Vec::new();
+        let mut skipped = Vec::new();
+
+        for file in files {
+            let file = match file {
+                Ok(file) => file,
+                Err(err) => {
+                    skipped.push(err);
+                    continue;
+                }
+            };
+            let (file_tests, file_skipped) = self.analyze_files(&[file], cache);
+            let found = file_tests.iter().any(|test| self.is_finding(test));
+            tests.extend(file_tests);
+            skipped.extend(file_skipped);
+            if found {
+                break;
+            }
+        }
+
+        (tests, skipped)
+    }
+
+    fn scan_directory(
+        &self,
+        directory: &Path,
+        cache: Option<&Cache>,
+    ) -> (Vec<Finding>, Vec<ScanError>) {
+        if directory.is_file() && archive::is_archive(directory) {
+            return self.scan_archive(directory);
+        }
+        let (files, mut skipped) = self.directory_files(directory);
+        let (tests, mut unreadable) = self.analyze_files(&files, cache);
+        skipped.append(&mut unreadable);
+        (tests, skipped)
+    }
+
+    /// Find all tests in the members of `archive` that pass all filters.
+    fn scan_archive(&self, archive: &Path) -> (Vec<Finding>, Vec<ScanError>) {
+        let members = match self.archive_members(archive) {
+            Ok(members) => members,
+            Err(err) => return (Vec::new(), vec![err]),
+        };
+        let results: Vec<(PathBuf, Result<Vec<TestFunction>, ScanError>)> = members
+            .into_par_iter()
+            .map(|Member { path, content }| {
+                let path = archive::member_path(archive, &path);
+                let result = match content {
+                    Ok(content) => self.parse(&path, &content),
+                    Err(err) => Err(ScanError::Io(path.clone(), err)),
+                };
+                if let Some(ProgressCallback(callback)) = &self.on_file_scanned {
+                    callback(&path);
+                }
+                (path, result)
+            })
+            .collect();
+        self.collect_findings(results)
+    }
+
+    /// Read the members of `archive` that pass all filters.
+    fn archive_members(&self, archive: &Path) -> Result<Vec<Member>, ScanError> {
+        archive::read_members(archive, |member| {
+            self.has_scanned_extension(member) && self.is_selected(member, member)
+        })
+        .map_err(|err| ScanError::Io(archive.to_path_buf(), err))
+    }
+
+    /// Python files within `directory` that pass all filters, in walk order.
+    fn directory_files(&self, directory: &Path) -> (Vec<PathBuf>, Vec<ScanError>) {
+        let mut files = Vec::new();
+        let mut skipped = Vec::new();
+
+        if directory.is_file() && archive::is_archive(directory) {
+            match self.archive_members(directory) {
+                Ok(members) => files.extend(
+                    members
+                        .iter()
+                        .map(|member| archive::member_path(directory, &member.path)),
+                ),
+                Err(err) => skipped.push(err),
+            }
+            return (files, skipped);
+        }
+
+        if directory.is_file() {
+            let file_name = Path::new(directory.file_name().unwrap_or_default());
+            if self.has_scanned_extension(directory) && self.is_selected(file_name, directory) {
+                files.push(directory.to_path_buf());
+            }
+            return (files, skipped);
+        }
+
+        for file in self.walk(directory) {
+            match file {
+                Ok(file) => files.push(file),
+                Err(err) => skipped.push(err),
+            }
+        }
+
+        (files, skipped)
+    }
+
+    /// Lazily walk `directory` for the Python files that pass all filters, in walk order.
+    fn walk<'a>(
+        &'a self,
+        directory: &'a Path,
+    ) -> impl Iterator<Item = Result<PathBuf, ScanError>> + 'a {
+        WalkBuilder::new(directory)
+            .standard_filters(self.respect_ignore_files)
+            // Exclusions owned by the directories themselves always apply
+            .add_custom_ignore_filename(COLLECT_IGNORE_FILE_NAME)
+            // Only ignore files decide what to skip; hidden files are scanned as before
+            .hidden(false)
+            // `.gitignore` applies outside of git repositories too
+            .require_git(false)
+            .max_depth(self.max_depth)
+            .follow_links(self.follow_symlinks)
+            .build()
+            .filter_map(move |entry| {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(err) => return Some(Err(walk_error(directory, err))),
+                };
+                if !self.has_scanned_extension(entry.path()) {
+                    return None;
+                }
+                let relative_path = entry.path().strip_prefix(directory).unwrap_or(entry.path());
+                self.is_selected(relative_path, entry.path())
+                    .then(|| Ok(entry.into_path()))
+            })
+    }
+
+    /// Analyze the given files instead of walking directories.
+    ///
+    /// Files are filtered by `include_paths` and `exclude_paths` as given and by
+    /// `skip_file_names`; findings are sorted by path and line.
+    pub fn scan_files(&self, files: &[PathBuf]) -> ScanReport {
+        let files: Vec<PathBuf> = files
+            .iter()
+            .filter(|file| self.is_selected(file, file))
+            .cloned()
+            .collect();
+        self.scan_walked_files(files)
+    }
+
+    /// Analyze files already known to be walked, e.g. by `is_walked`, without filtering
+    /// them again; findings are sorted by path and line.
+    pub fn scan_walked_files(&self, files: Vec<PathBuf>) -> ScanReport {
+        let cache = self.load_cache();
+        let (tests, skipped) = if self.fail_fast {
+            self.analyze_until_finding(files.into_iter().map(Ok), cache.as_ref())
+        } else {
+            self.analyze_files(&files, cache.as_ref())
+        };
+        save_cache(cache);
+        self.report(tests, skipped)
+    }
+
+    fn load_cache(&self) -> Option<Cache> {
+        self.cache_dir
+            .as_deref()
+            .map(|dir| Cache::load(dir, self.parser, &self.parse_options))
+    }
+
+    /// Whether walking `directory` would yield the file at `path`, which need not exist
+    /// (anymore): it must be within `max_depth`, not be excluded by the ignore files of
+    /// its ancestors and pass the path and file name filters.
+    ///
+    /// Global git excludes aren't consulted.
+    pub fn is_walked(&self, directory: &Path, path: &Path) -> bool {
+        let Ok(relative_path) = path.strip_prefix(directory) else {
+            return false;
+        };
+        let within_depth = self
+            .max_depth
+            .is_none_or(|depth| relative_path.components().count() <= depth);
+
+        within_depth
+            && self.has_scanned_extension(path)
+            && !self.is_ignored(path)
+            && self.is_selected(relative_path, path)
+    }
+
+    /// Whether the ignore files of the ancestors of `path` exclude it, the closest one
+    /// that matches deciding, like when walking.
+    fn is_ignored(&self, path: &Path) -> bool {
+        let file_names: &[&str] = if self.respect_ignore_files {
+            // In increasing precedence, as when walking
+            &[".gitignore", ".ignore", COLLECT_IGNORE_FILE_NAME]
+        } else {
+            &[COLLECT_IGNORE_FILE_NAME]
+        };
+
+        for directory in path.ancestors().skip(1) {
+            let mut builder = GitignoreBuilder::new(directory);
+            for file_name in file_names {
+                let ignore_file = directory.join(file_name);
+                if ignore_file.is_file() {
+                    builder.add(ignore_file);
+                }
+            }
+            let Ok(ignore) = builder.build() else {
+                continue;
+            };
+            let matched = ignore.matched_path_or_any_parents(path, false);
+            if !matched.is_none() {
+                return matched.is_ignore();
+            }
+        }
+        false
+    }
+
+    /// Whether `path` has one of the extensions of files to scan.
+    pub fn has_scanned_extension(&self, path: &Path) -> bool {
+        path.extension().is_some_and(|ext| {
+            self.extensions
+                .iter()
+                .any(|extension| ext == extension.as_str())
+        })
+    }
+
+    /// Whether the file at `path`, found at `relative_path` within the scanned directory,
+    /// passes the path and file name filters.
+    fn is_selected(&self, relative_path: &Path, path: &Path) -> bool {
+        let included = self
+            .include_paths
+            .as_ref()
+            .is_none_or(|globs| globs.is_match(relative_path));
+        let skipped_name = path
+            .file_name()
+            .is_some_and(|name| self.skip_file_names.is_match(name));
+
+        included && !self.exclude_paths.is_match(relative_path) && !skipped_name
+    }
+
+    /// Whether tests carrying `marker` are excluded from the findings.
+    fn is_excluded_marker(&self, marker: &str) -> bool {
+        self.exclude_markers.contains(marker)
+            || self
+                .exclude_marker_patterns
+                .iter()
+                .any(|pattern| pattern.is_match(marker))
+    }
+
+    /// The counted markers of `test` that exclude it from the findings.
+    pub fn excluded_by<'a>(&self, test: &'a Finding) -> Vec<&'a str> {
+        let mut markers: Vec<&str> = self
+            .marker_source
+            .markers(&test.marker_sources)
+            .filter(|marker| self.is_excluded_marker(marker))
+            .map(String::as_str)
+            .collect();
+        markers.sort_unstable();
+        markers.dedup();
+        markers
+    }
+
+    /// The number of distinct markers of `test` counted by the marker source.
+    fn counted_markers(&self, test: &Finding) -> usize {
+        self.marker_source
+            .markers(&test.marker_sources)
+            .collect::<HashSet<_>>()
+            .len()
+    }
+
+    /// Whether `test` is reported: it carries too few markers, or none of the excluded ones.
+    fn is_finding(&self, test: &Finding) -> bool {
+        match self.min_markers {
+            Some(min_markers) => self.counted_markers(test) < min_markers,
+            None => self.excluded_by(test).is_empty(),
+        }
+    }
+
+    fn report(&self, mut tests: Vec<Finding>, skipped: Vec<ScanError>) -> ScanReport {
+        let stats = MarkerStats::from_tests(&tests);
+        if self.min_markers.is_some() {
+            for test in &mut tests {
+                test.marker_count = Some(self.counted_markers(test));
+            }
+        }
+        let mut findings: Vec<Finding> = tests
+            .iter()
+            .filter(|test| self.is_finding(test))
+            .cloned()
+            .collect();
+        if self.fail_fast {
+            findings.truncate(1);
+        }
+
+        ScanReport {
+            findings,
+            tests,
+            skipped,
+            stats,
+        }
+    }
+
+    /// Find all tests in `files`, sorted by path and line.
+    fn analyze_files(
+        &self,
+        files: &[PathBuf],
+        cache: Option<&Cache>,
+    ) -> (Vec<Finding>, Vec<ScanError>) {
+        let results: Vec<(PathBuf, Result<Vec<TestFunction>, ScanError>)> = files
+            .par_iter()
+            .map(|path| {
+                let result = match cache {
+                    Some(cache) => self.analyze_file_cached(path, cache),
+                    None => self.analyze_file(path),
+                };
+                if let Some(ProgressCallback(callback)) = &self.on_file_scanned {
+                    callback(path);
+                }
+                (path.clone(), result)
+            })
+            .collect();
+        self.collect_findings(results)
+    }
+
+    /// Turn the tests found per file into findings, sorted by path and line.
+    fn collect_findings(
+        &self,
+        results: Vec<(PathBuf, Result<Vec<TestFunction>, ScanError>)>,
+    ) -> (Vec<Finding>, Vec<ScanError>) {
+        let mut tests = Vec::new();
+        let mut skipped = Vec::new();
+        for (path, result) in results {
+            match result {
+                Ok(functions) => tests.extend(
+                    functions
+                        .into_iter()
+                        .filter(|test| !self.is_excluded_class_member(test))
+                        .map(|test| Finding {
+                            file: path.clone(),
+                            line: test.line,
+                            class_path: test.class_path,
+                            function: test.name,
+                            markers: test.markers,
+                            marker_sources: test.marker_sources,
+                            cases: test.cases,
+                            marker_count: None,
+                        }),
+                ),
+                Err(error) => skipped.push(error),
+            }
+        }
+
+        // Keep the output independent of traversal and thread scheduling order
+        tests.sort_by(|a, b| a.file.cmp(&b.file).then(a.line.cmp(&b.line)));
+        skipped.sort_by(|a: &ScanError, b| a.path().cmp(b.path()));
+
+        (tests, skipped)
+    }
+
+    fn is_excluded_class_member(&self, test: &TestFunction) -> bool {
+        test.class_path
+            .iter()
+            .any(|class| self.exclude_classes.is_match(class))
+    }
+
+    /// Reuse the tests found by an earlier run if the file is unchanged since.
+    fn analyze_file_cached(
+        &self,
+        path: &Path,
+        cache: &Cache,
+    ) -> Result<Vec<TestFunction>, ScanError> {
+        // Take the stamp before reading, so a concurrent change invalidates the entry
+        let Some(stamp) = Cache::stamp(path) else {
+            return self.analyze_file(path);
+        };
+        if let Some(tests) = cache.get(path, stamp) {
+            return Ok(tests);
+        }
+
+        let tests = self.analyze_file(path)?;
+        cache.insert(path, stamp, &tests);
+        Ok(tests)
+    }
+
+    fn analyze_file(&self, path: &Path) -> Result<Vec<TestFunction>, ScanError> {
+        let io_error = |err| ScanError::Io(path.to_path_buf(), err);
+
+        if self.parser == ParserKind::Regex {
+            let file = File::open(path).map_err(io_error)?;
+            let size = file.metadata().map_err(io_error)?.len();
+            if size > self.stream_threshold {
+                return collect_python_tests_from_reader(BufReader::new(file), &self.parse_options)
+                    .map_err(io_error);
+            }
+        }
+
+        let content = std::fs::read_to_string(path).map_err(io_error)?;
+        self.parse(path, &content)
+    }
+
+    /// Find the tests in `content`, read from `path`, with the configured parser.
+    fn parse(&self, path: &Path, content: &str) -> Result<Vec<TestFunction>, ScanError> {
+        // Files saved by some Windows editors start with a UTF-8 byte order mark
+        let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+
+        match self.parser {
+            ParserKind::Regex => Ok(collect_python_tests(content, &self.parse_options)),
+            ParserKind::Ast => ast_parser::collect_python_tests(content, &self.parse_options)
+                .map_err(|err| ScanError::Parse(path.to_path_buf(), err)),
+        }
+    }
+}
+
+/// The tests not reported yet under another path, e.g. from an overlapping directory.
+///
+/// `seen` holds the canonical path and line of every test reported so far. Tests are
+/// grouped by file, so each file's path is only canonicalized once.
+fn unseen_tests(tests: Vec<Finding>, seen: &mut HashSet<(PathBuf, usize)>) -> Vec<Finding> {
+    let mut canonical: Option<(PathBuf, PathBuf)> = None;
+    tests
+        .into_iter()
+        .filter(|test| {
+            if canonical
+                .as_ref()
+                .is_none_or(|(file, _)| *file != test.file)
+            {
+                let file = std::fs::canonicalize(&test.file).unwrap_or_else(|_| test.file.clone());
+                canonical = Some((test.file.clone(), file));
+            }
+            let (_, file) = canonical.as_ref().unwrap();
+            seen.insert((file.clone(), test.line))
+        })
+        .collect()
+}
+
+fn save_cache(cache: Option<Cache>) {
+    // A cache that can't be written only costs the next run its speedup
+    if let Some(cache) = cache {
+        let _ = cache.save();
+    }
+}
+
+/// Attribute an error from walking `directory` to the path it occurred at, if known.
+fn walk_error(directory: &Path, err: ignore::Error) -> ScanError {
+    match err {
+        ignore::Error::WithPath { path, err } => ScanError::Walk(path, err.to_string()),
+        ignore::Error::WithDepth { err, .. } => walk_error(directory, *err),
+        ignore::Error::Loop { ancestor, child } => ScanError::Walk(
+            child,
+            format!("symlink loop back to {}", ancestor.display()),
+        ),
+        err => ScanError::Walk(directory.to_path_buf(), err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use globset::{Glob, GlobSetBuilder};
+
+    fn write_file(dir: &Path, relative_path: &str, content: &str) {
+        let path = dir.join(relative_path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    fn functions(findings: &[Finding]) -> Vec<&str> {
+        findings.iter().map(|f| f.function.as_str()).collect()
+    }
+
+    fn scanner(dir: &Path) -> Scanner {
+        let exclude_markers: HashSet<String> = ["unit"].iter().map(|s| s.to_string()).collect();
+        Scanner::new(exclude_markers).directories([dir])
+    }
+
+    #[test]
+    fn test_respects_ignore_files() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(dir.path(), ".ignore", ".venv/\n");
+        write_file(dir.path(), ".gitignore", "build/\n");
+        write_file(dir.path(), "test_a.py", "def test_a():\n    pass\n");
+        write_file(
+            dir.path(),
+            ".venv/lib/test_vendored.py",
+            "def test_vendored():\n    pass\n",
+        );
+        write_file(
+            dir.path(),
+            "build/test_generated.py",
+            "def test_generated():\n    pass\n",
+        );
+
+        let result = scanner(dir.path()).scan().findings;
+        assert_eq!(functions(&result), vec!["test_a"]);
+        assert_eq!(result[0].file, dir.path().join("test_a.py"));
+        assert_eq!(result[0].line, 1);
+
+        let findings = scanner(dir.path())
+            .respect_ignore_files(false)
+            .scan()
+            .findings;
+        let mut result = functions(&findings);
+        result.sort_unstable();
+        assert_eq!(result, vec!["test_a", "test_generated", "test_vendored"]);
+    }
+
+    #[test]
+    fn test_collectignore_files() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(dir.path(), "test_a.py", "def test_a():\n    pass\n");
+        write_file(
+            dir.path(),
+            "sub/.collectignore",
+            "# owned by sub\ntest_gen_*.py\nlegacy/\n",
+        );
+        write_file(dir.path(), "sub/test_b.py", "def test_b():\n    pass\n");
+        write_file(
+            dir.path(),
+            "sub/test_gen_b.py",
+            "def test_gen_b():\n    pass\n",
+        );
+        write_file(
+            dir.path(),
+            "sub/legacy/test_c.py",
+            "def test_c():\n    pass\n",
+        );
+        write_file(dir.path(), "test_gen_a.py", "def test_gen_a():\n    pass\n");
+
+        let expected = vec!["test_b", "test_a", "test_gen_a"];
+        assert_eq!(functions(&scanner(dir.path()).scan().findings), expected);
+        assert_eq!(
+            functions(
+                &scanner(dir.path())
+                    .respect_ignore_files(false)
+                    .scan()
+                    .findings
+            ),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_directories_reported_in_given_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let packages = ["pkg_c", "pkg_a", "pkg_d", "pkg_b"];
+        for package in packages {
+            write_file(
+                dir.path(),
+                &format!("{package}/test_{package}.py"),
+                &format!("def test_{package}():\n    pass\n"),
+            );
+        }
+
+        let exclude_markers = HashSet::from(["unit".to_string()]);
+        let result = Scanner::new(exclude_markers)
+            .directories(packages.map(|package| dir.path().join(package)))
+            .scan()
+            .findings;
+        assert_eq!(
+            functions(&result),
+            vec!["test_pkg_c", "test_pkg_a", "test_pkg_d", "test_pkg_b"]
+        );
+    }
+
+    #[test]
+    fn test_files() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(dir.path(), ".ignore", "ignored/\n");
+        write_file(dir.path(), "test_a.py", "def test_a():\n    pass\n");
+        write_file(dir.path(), "sub/test_b.py", "def test_b(:\n");
+        write_file(dir.path(), "sub/notes.txt", "");
+        write_file(dir.path(), "ignored/test_c.py", "def test_c():\n    pass\n");
+
+        let (mut files, skipped) = scanner(dir.path())
+            .directories([dir.path().join("sub")])
+            .files();
+        files.sort();
+
+        assert_eq!(
+            files,
+            vec![
+                dir.path().join("sub/test_b.py"),
+                dir.path().join("test_a.py")
+            ]
+        );
+        assert!(skipped.is_empty());
+    }
+
+    #[test]
+    fn test_single_file() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(dir.path(), "test_a.py", "def test_a():\n    pass\n");
+        write_file(dir.path(), "test_b.py", "def test_b():\n    pass\n");
+        let file = dir.path().join("test_a.py");
+
+        let result = scanner(&file).scan().findings;
+        assert_eq!(functions(&result), vec!["test_a"]);
+        assert_eq!(result[0].file, file);
+
+        let include_paths = GlobSetBuilder::new()
+            .add(Glob::new("test_a.py").unwrap())
+            .build()
+            .unwrap();
+        let result = scanner(&file).include_paths(include_paths).scan().findings;
+        assert_eq!(functions(&result), vec!["test_a"]);
+    }
+
+    #[test]
+    fn test_archives() {
+        let dir = tempfile::tempdir().unwrap();
+        let members = [
+            ("pkg/tests/test_a.py", "def test_a():\n    pass\n"),
+            ("pkg/tests/conftest.txt", "def test_ignored():\n    pass\n"),
+            (
+                "pkg/tests/test_b.py",
+                "@pytest.mark.unit\ndef test_b():\n    pass\n",
+            ),
+        ];
+
+        let tar_gz = dir.path().join("pkg.tar.gz");
+        let encoder = flate2::write::GzEncoder::new(
+            std::fs::File::create(&tar_gz).unwrap(),
+            flate2::Compression::default(),
+        );
+        let mut builder = tar::Builder::new(encoder);
+        for (path, content) in members {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            builder
+                .append_data(&mut header, path, content.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let zip_file = dir.path().join("pkg.zip");
+        let mut writer = zip::ZipWriter::new(std::fs::File::create(&zip_file).unwrap());
+        for (path, content) in members {
+            writer
+                .start_file(path, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            std::io::Write::write_all(&mut writer, content.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+
+        for archive in [&tar_gz, &zip_file] {
+            let result = scanner(archive).scan().findings;
+            assert_eq!(functions(&result), vec!["test_a"]);
+            assert_eq!(
+                result[0].file,
+                PathBuf::from(format!("{}!pkg/tests/test_a.py", archive.display()))
+            );
+
+            let (files, skipped) = scanner(archive).files();
+            assert_eq!(files.len(), 2);
+            assert!(skipped.is_empty());
+
+            let exclude_paths = GlobSetBuilder::new()
+                .add(Glob::new("**/test_a.py").unwrap())
+                .build()
+                .unwrap();
+            let result = scanner(archive).exclude_paths(exclude_paths).scan();
+            assert!(result.tests.iter().all(|test| test.function == "test_b"));
+        }
+
+        let broken = dir.path().join("broken.zip");
+        std::fs::write(&broken, "not a zip").unwrap();
+        let report = scanner(&broken).scan();
+        assert!(report.findings.is_empty());
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(report.skipped[0].path(), broken);
+    }
+
+    #[test]
+    fn test_stream_large_files() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(
+            dir.path(),
+            "test_a.py",
+            "@pytest.mark.unit\ndef test_a():\n    pass\n\ndef test_b():\n    pass\n",
+        );
+
+        let read = scanner(dir.path()).scan().findings;
+        let streamed = scanner(dir.path()).stream_threshold(0).scan().findings;
+        assert_eq!(functions(&streamed), vec!["test_b"]);
+        assert_eq!(streamed, read);
+    }
+
+    #[test]
+    fn test_extensions() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(dir.path(), "test_a.py", "def test_a():\n    pass\n");
+        write_file(dir.path(), "test_b.pyx", "def test_b():\n    pass\n");
+        write_file(dir.path(), "test_c.txt", "def test_c():\n    pass\n");
+
+        let result = scanner(dir.path()).scan().findings;
+        assert_eq!(functions(&result), vec!["test_a"]);
+
+        let result = scanner(dir.path())
+            .extensions(vec!["py".to_string(), "pyx".to_string()])
+            .scan()
+            .findings;
+        assert_eq!(functions(&result), vec!["test_a", "test_b"]);
+    }
+
+    #[test]
+    fn test_exclude_path_globs() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(dir.path(), "test_a.py", "def test_a():\n    pass\n");
+        write_file(
+            dir.path(),
+            "fixtures/sample.py",
+            "def test_sample():\n    pass\n",
+        );
+        write_file(
+            dir.path(),
+            "generated/test_gen.py",
+            "def test_gen():\n    pass\n",
+        );
+
+        let exclude_paths = GlobSetBuilder::new()
+            .add(Glob::new("fixtures/**").unwrap())
+            .add(Glob::new("generated/*.py").unwrap())
+            .build()
+            .unwrap();
+
+        let result = scanner(dir.path())
+            .exclude_paths(exclude_paths)
+            .scan()
+            .findings;
+
+        assert_eq!(functions(&result), vec!["test_a"]);
+    }
+
+    #[test]
+    fn test_include_path_globs() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(dir.path(), "test_api_a.py", "def test_a():\n    pass\n");
+        write_file(dir.path(), "sub/test_api_b.py", "def test_b():\n    pass\n");
+        write_file(
+            dir.path(),
+            "legacy/test_api_c.py",
+            "def test_c():\n    pass\n",
+        );
+        write_file(dir.path(), "test_other.py", "def test_other():\n    pass\n");
+
+        let include_paths = GlobSetBuilder::new()
+            .add(Glob::new("**/test_api_*.py").unwrap())
+            .build()
+            .unwrap();
+        let exclude_paths = GlobSetBuilder::new()
+            .add(Glob::new("legacy/**").unwrap())
+            .build()
+            .unwrap();
+
+        let result = scanner(dir.path())
+            .include_paths(include_paths)
+            .exclude_paths(exclude_paths)
+            .scan()
+            .findings;
+
+        assert_eq!(functions(&result), vec!["test_b", "test_a"]);
+    }
+
+    #[test]
+    fn test_skip_file_names() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(dir.path(), "test_a.py", "def test_a():\n    pass\n");
+        write_file(dir.path(), "test_gen_a.py", "def test_gen_a():\n    pass\n");
+        write_file(
+            dir.path(),
+            "sub/test_gen_b.py",
+            "def test_gen_b():\n    pass\n",
+        );
+        write_file(
+            dir.path(),
+            "test_gen/test_c.py",
+            "def test_c():\n    pass\n",
+        );
+
+        let skip_file_names = GlobSetBuilder::new()
+            .add(Glob::new("test_gen_*.py").unwrap())
+            .build()
+            .unwrap();
+
+        let result = scanner(dir.path())
+            .skip_file_names(skip_file_names)
+            .scan()
+            .findings;
+
+        assert_eq!(functions(&result), vec!["test_a", "test_c"]);
+    }
+
+    #[test]
+    fn test_exclude_classes() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(
+            dir.path(),
+            "test_a.py",
+            "class TestBase:\n    def test_base(self):\n        pass\n\n    class TestNested:\n        def test_nested(self):\n            pass\n\nclass TestFoo(TestBase):\n    def test_foo(self):\n        pass\n\ndef test_a():\n    pass\n",
+        );
+
+        let exclude_classes = GlobSetBuilder::new()
+            .add(Glob::new("TestBase").unwrap())
+            .build()
+            .unwrap();
+
+        let report = scanner(dir.path()).exclude_classes(exclude_classes).scan();
+
+        assert_eq!(functions(&report.findings), vec!["test_foo", "test_a"]);
+        assert_eq!(report.tests, report.findings);
+    }
+
+    #[test]
+    fn test_on_file_scanned() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(dir.path(), "test_a.py", "def test_a():\n    pass\n");
+        write_file(dir.path(), "sub/test_b.py", "def test_b():\n    pass\n");
+        write_file(dir.path(), "sub/helper.txt", "");
+
+        let scanned = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let callback_scanned = Arc::clone(&scanned);
+        scanner(dir.path())
+            .on_file_scanned(move |path| callback_scanned.lock().unwrap().push(path.to_owned()))
+            .scan();
+
+        let mut scanned = scanned.lock().unwrap().clone();
+        scanned.sort();
+        assert_eq!(
+            scanned,
+            vec![
+                dir.path().join("sub/test_b.py"),
+                dir.path().join("test_a.py")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_dir = dir.path().join(".cache");
+        let tests_dir = dir.path().join("tests");
+        let path = tests_dir.join("test_a.py");
+        write_file(&tests_dir, "test_a.py", "def test_a():\n    pass\n");
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+
+        let scanner = scanner(&tests_dir).cache_dir(Some(cache_dir.clone()));
+        assert_eq!(functions(&scanner.scan().findings), vec!["test_a"]);
+        assert!(cache_dir.join("scan-cache.json").is_file());
+
+        // Same size and modification time: the cached result is reused
+        write_file(&tests_dir, "test_a.py", "def test_b():\n    pass\n");
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert_eq!(functions(&scanner.scan().findings), vec!["test_a"]);
+
+        // Other parser settings don't reuse the cache
+        let result = scanner.clone().parser(ParserKind::Ast).scan().findings;
+        assert_eq!(functions(&result), vec!["test_b"]);
+
+        write_file(&tests_dir, "test_a.py", "def test_c():\n    pass\n\n");
+        assert_eq!(functions(&scanner.scan().findings), vec!["test_c"]);
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(dir.path(), "test_a.py", "def test_a():\n    pass\n");
+        write_file(dir.path(), "sub/test_b.py", "def test_b():\n    pass\n");
+        write_file(
+            dir.path(),
+            "sub/deep/test_c.py",
+            "def test_c():\n    pass\n",
+        );
+
+        let result = scanner(dir.path()).max_depth(Some(1)).scan().findings;
+        assert_eq!(functions(&result), vec!["test_a"]);
+
+        let result = scanner(dir.path()).max_depth(Some(2)).scan().findings;
+        assert_eq!(functions(&result), vec!["test_b", "test_a"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_follow_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(
+            dir.path(),
+            "shared/test_shared.py",
+            "def test_shared():\n    pass\n",
+        );
+        write_file(dir.path(), "tests/test_a.py", "def test_a():\n    pass\n");
+        std::os::unix::fs::symlink(dir.path().join("shared"), dir.path().join("tests/shared"))
+            .unwrap();
+        std::os::unix::fs::symlink(dir.path().join("tests"), dir.path().join("tests/loop"))
+            .unwrap();
+
+        let report = scanner(&dir.path().join("tests")).scan();
+        assert_eq!(functions(&report.findings), vec!["test_a"]);
+        assert!(report.skipped.is_empty());
+
+        let report = scanner(&dir.path().join("tests"))
+            .follow_symlinks(true)
+            .scan();
+        assert_eq!(functions(&report.findings), vec!["test_shared", "test_a"]);
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(report.skipped[0].path(), dir.path().join("tests/loop"));
+    }
+
+    #[test]
+    fn test_overlapping_directories_are_deduplicated() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(dir.path(), "tests/test_a.py", "def test_a():\n    pass\n");
+        write_file(dir.path(), "test_b.py", "def test_b():\n    pass\n");
+
+        let result = scanner(dir.path())
+            .directories([dir.path().join("./tests"), dir.path().join("tests")])
+            .scan()
+            .findings;
+
+        assert_eq!(functions(&result), vec!["test_b", "test_a"]);
+        assert_eq!(result[1].file, dir.path().join("tests/test_a.py"));
+
+        // Stopping at the first finding doesn't change which tests are duplicates
+        let marked = "@pytest.mark.unit\ndef test_marked():\n    pass\n";
+        write_file(dir.path(), "tests/test_a.py", marked);
+        write_file(dir.path(), "test_b.py", marked);
+        let report = scanner(dir.path())
+            .directories([dir.path().join("tests")])
+            .fail_fast(true)
+            .scan();
+        assert!(report.findings.is_empty());
+        assert_eq!(report.tests.len(), 2);
+    }
+
+    #[test]
+    fn test_scan_files() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(dir.path(), "test_b.py", "def test_b():\n    pass\n");
+        write_file(dir.path(), "test_a.py", "def test_a():\n    pass\n");
+        write_file(dir.path(), "test_c.py", "def test_c():\n    pass\n");
+
+        let result = scanner(dir.path())
+            .scan_files(&[dir.path().join("test_b.py"), dir.path().join("test_a.py")])
+            .findings;
+
+        assert_eq!(functions(&result), vec!["test_a", "test_b"]);
+    }
+
+    #[test]
+    fn test_is_walked_matches_walk() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(dir.path(), ".gitignore", "build/\n");
+        write_file(dir.path(), "legacy/.collectignore", "test_old.py\n");
+        let files = [
+            "test_a.py",
+            "build/test_generated.py",
+            "legacy/test_old.py",
+            "legacy/test_new.py",
+            "fixtures/test_data.py",
+            "test_a.txt",
+        ];
+        for file in files {
+            write_file(dir.path(), file, "def test_a():\n    pass\n");
+        }
+        let mut exclude_paths = GlobSetBuilder::new();
+        exclude_paths.add(Glob::new("fixtures/**").unwrap());
+        let scanner = scanner(dir.path()).exclude_paths(exclude_paths.build().unwrap());
+
+        let walked: BTreeSet<PathBuf> = scanner.walk(dir.path()).map(Result::unwrap).collect();
+        assert_eq!(walked.len(), 2);
+        for file in files {
+            let path = dir.path().join(file);
+            assert_eq!(scanner.is_walked(dir.path(), &path), walked.contains(&path));
+        }
+        assert!(!scanner.is_walked(&dir.path().join("legacy"), &dir.path().join("test_a.py")));
+    }
+
+    #[test]
+    fn test_byte_order_mark_and_crlf() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(
+            dir.path(),
+            "test_windows.py",
+            "\u{feff}@pytest.mark.unit\r\ndef test_marked():\r\n    pass\r\n\r\n\
+             def test_unmarked():\r\n    pass\r\n",
+        );
+
+        for parser in [ParserKind::Regex, ParserKind::Ast] {
+            let result = scanner(dir.path()).parser(parser).scan().findings;
+            assert_eq!(functions(&result), vec!["test_unmarked"]);
+            assert_eq!(result[0].line, 5);
+        }
+    }
+
+    #[test]
+    fn test_reports_unreadable_files() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(dir.path(), "test_a.py", "def test_a():\n    pass\n");
+        std::fs::write(dir.path().join("test_latin1.py"), b"# caf\xe9\n").unwrap();
+        write_file(dir.path(), "test_syntax.py", "def test_x(:\n");
+
+        let report = scanner(dir.path()).scan();
+        assert_eq!(functions(&report.findings), vec!["test_a", "test_x"]);
+        assert_eq!(report.skipped.len(), 1);
+        assert!(matches!(report.skipped[0], ScanError::Io(..)));
+        assert_eq!(report.skipped[0].path(), dir.path().join("test_latin1.py"));
+
+        let report = scanner(dir.path()).parser(ParserKind::Ast).scan();
+        assert_eq!(functions(&report.findings), vec!["test_a"]);
+        let skipped: Vec<&Path> = report.skipped.iter().map(ScanError::path).collect();
+        assert_eq!(
+            skipped,
+            vec![
+                dir.path().join("test_latin1.py"),
+                dir.path().join("test_syntax.py")
+            ]
+        );
+        assert!(matches!(report.skipped[1], ScanError::Parse(..)));
+
+        let err = scanner(dir.path()).try_scan().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "{}: stream did not contain valid UTF-8",
+                dir.path().join("test_latin1.py").display()
+            )
+        );
+        std::fs::remove_file(dir.path().join("test_latin1.py")).unwrap();
+        assert_eq!(
+            functions(&scanner(dir.path()).try_scan().unwrap()),
+            vec!["test_a", "test_x"]
+        );
+    }
+
+    #[test]
+    fn test_exclude_marker_patterns() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(
+            dir.path(),
+            "test_a.py",
+            "@pytest.mark.feature_x\ndef test_a():\n    pass\n\n@pytest.mark.unit\ndef test_b():\n    pass\n\n@pytest.mark.new_feature_x\ndef test_c():\n    pass\n\ndef test_d():\n    pass\n",
+        );
+
+        let result = scanner(dir.path())
+            .exclude_marker_patterns(vec![Regex::new("^feature_").unwrap()])
+            .scan()
+            .findings;
+
+        assert_eq!(functions(&result), vec!["test_c", "test_d"]);
+    }
+
+    #[test]
+    fn test_marker_source() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(
+            dir.path(),
+            "test_a.py",
+            "pytestmark = pytest.mark.unit\n\n@pytest.mark.unit\nclass TestFoo:\n    def test_in_class(self):\n        pass\n\n    @pytest.mark.unit\n    def test_marked(self):\n        pass\n\ndef test_in_module():\n    pass\n",
+        );
+
+        let findings = |source| {
+            functions(&scanner(dir.path()).marker_source(source).scan().findings)
+                .into_iter()
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+
+        assert!(findings(MarkerSource::All).is_empty());
+        assert!(findings(MarkerSource::Module).is_empty());
+        assert_eq!(
+            findings(MarkerSource::Class),
+            vec!["test_in_module".to_string()]
+        );
+        assert_eq!(
+            findings(MarkerSource::Function),
+            vec!["test_in_class".to_string(), "test_in_module".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_min_markers() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(
+            dir.path(),
+            "test_a.py",
+            "pytestmark = pytest.mark.billing\n\n@pytest.mark.unit\ndef test_two():\n    pass\n\ndef test_one():\n    pass\n\n@pytest.mark.billing\ndef test_repeated():\n    pass\n",
+        );
+
+        let report = scanner(dir.path()).min_markers(Some(2)).scan();
+        assert_eq!(
+            functions(&report.findings),
+            vec!["test_one", "test_repeated"]
+        );
+        assert_eq!(report.findings[0].marker_count, Some(1));
+        assert_eq!(report.tests[0].marker_count, Some(2));
+
+        let report = scanner(dir.path())
+            .min_markers(Some(1))
+            .marker_source(MarkerSource::Function)
+            .scan();
+        assert_eq!(functions(&report.findings), vec!["test_one"]);
+        assert_eq!(report.findings[0].marker_count, Some(0));
+
+        let report = scanner(dir.path()).scan();
+        assert!(report.tests.iter().all(|test| test.marker_count.is_none()));
+    }
+
+    #[test]
+    fn test_fail_fast() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(
+            dir.path(),
+            "first/test_a.py",
+            "@pytest.mark.unit\ndef test_marked():\n    pass\n",
+        );
+        write_file(
+            dir.path(),
+            "first/sub/test_b.py",
+            "def test_one():\n    pass\n\ndef test_two():\n    pass\n",
+        );
+        write_file(
+            dir.path(),
+            "second/test_c.py",
+            "def test_three():\n    pass\n",
+        );
+
+        let report = Scanner::new(HashSet::from(["unit".to_string()]))
+            .directories([dir.path().join("first"), dir.path().join("second")])
+            .fail_fast(true)
+            .scan();
+        assert_eq!(functions(&report.findings), vec!["test_one"]);
+        // The second directory is never scanned
+        assert!(
+            report
+                .tests
+                .iter()
+                .all(|test| test.file.starts_with(dir.path().join("first")))
+        );
+
+        let files = [
+            dir.path().join("first/test_a.py"),
+            dir.path().join("second/test_c.py"),
+            dir.path().join("first/sub/test_b.py"),
+        ];
+        let report = scanner(dir.path()).fail_fast(true).scan_files(&files);
+        assert_eq!(functions(&report.findings), vec!["test_three"]);
+        assert_eq!(functions(&report.tests), vec!["test_marked", "test_three"]);
+
+        let report = scanner(dir.path())
+            .fail_fast(true)
+            .min_markers(Some(1))
+            .scan();
+        assert_eq!(report.findings.len(), 1);
+    }
+
+    #[test]
+    fn test_marker_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(
+            dir.path(),
+            "test_a.py",
+            r#"
+import pytest
+
+@pytest.mark.unit
+def test_unit():
+    pass
+
+@pytest.mark.unit
+@pytest.mark.slow
+def test_unit_slow():
+    pass
+
+@pytest.mark.e2e
+def test_e2e():
+    pass
+
+def test_plain():
+    pass
+"#,
+        );
+
+        let report = scanner(dir.path()).scan();
+
+        assert_eq!(functions(&report.findings), vec!["test_e2e", "test_plain"]);
+        assert_eq!(report.stats.tests, 4);
+        assert_eq!(report.stats.without_markers, 1);
+        assert_eq!(
+            report.stats.markers,
+            BTreeMap::from([
+                ("e2e".to_string(), 1),
+                ("slow".to_string(), 1),
+                ("unit".to_string(), 2)
+            ])
+        );
+    }
+}
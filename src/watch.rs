@@ -0,0 +1,135 @@
+//! Re-scanning changed files while watching the scanned directories (`--watch`).
+
+use collect_unmarked_tests::{Finding, Scanner};
+use notify::{Event, RecursiveMode, Watcher};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// How long to wait for further changes before re-scanning, so that e.g. saving many
+/// files at once triggers a single update.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Watch `directories` and call `report` with all findings after every change.
+///
+/// Only the changed files are scanned again; `findings` are those of the initial scan,
+/// with their paths as the scanner reported them. Runs until the watcher fails.
+pub fn watch(
+    scanner: &Scanner,
+    directories: &[PathBuf],
+    findings: Vec<Finding>,
+    mut report: impl FnMut(&[Finding]),
+) -> notify::Result<()> {
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    for directory in directories {
+        watcher.watch(directory, RecursiveMode::Recursive)?;
+    }
+
+    let cwd = std::env::current_dir().unwrap_or_default();
+    // Events carry absolute paths
+    let directories: Vec<PathBuf> = directories
+        .iter()
+        .map(|directory| cwd.join(directory))
+        .collect();
+    let mut findings = WatchedFindings::new(findings, cwd.clone());
+
+    while let Ok(event) = receiver.recv() {
+        let mut changed = BTreeSet::new();
+        collect_changed_files(scanner, &directories, event?, &cwd, &mut changed);
+        while let Ok(event) = receiver.recv_timeout(DEBOUNCE) {
+            collect_changed_files(scanner, &directories, event?, &cwd, &mut changed);
+        }
+        if changed.is_empty() {
+            continue;
+        }
+
+        report(&findings.rescan(scanner, changed));
+    }
+
+    Ok(())
+}
+
+/// The findings of the watched files by file, kept up to date as files change.
+pub struct WatchedFindings {
+    cwd: PathBuf,
+    by_file: BTreeMap<PathBuf, Vec<Finding>>,
+}
+
+impl WatchedFindings {
+    pub fn new(findings: Vec<Finding>, cwd: PathBuf) -> Self {
+        let mut watched = Self {
+            cwd,
+            by_file: BTreeMap::new(),
+        };
+        watched.insert(findings);
+        watched
+    }
+
+    /// Scan the `changed` files again, given relative to the working directory and already
+    /// filtered like when walking, replacing their findings; returns the findings of all
+    /// files.
+    pub fn rescan(&mut self, scanner: &Scanner, changed: BTreeSet<PathBuf>) -> Vec<Finding> {
+        let mut existing = Vec::new();
+        for path in changed {
+            self.by_file.remove(&path);
+            if path.is_file() {
+                existing.push(path);
+            }
+        }
+
+        let rescan = scanner.scan_walked_files(existing);
+        for skipped in &rescan.skipped {
+            eprintln!("warning: skipping {}", skipped);
+        }
+        self.insert(rescan.findings);
+
+        self.by_file.values().flatten().cloned().collect()
+    }
+
+    fn insert(&mut self, findings: Vec<Finding>) {
+        for finding in findings {
+            self.by_file
+                .entry(normalize(&finding.file, &self.cwd))
+                .or_default()
+                .push(finding);
+        }
+    }
+}
+
+/// Add the files that were created, modified or removed in `event` and that `scanner`
+/// would walk in one of the (absolute) `directories` to `changed`.
+fn collect_changed_files(
+    scanner: &Scanner,
+    directories: &[PathBuf],
+    event: Event,
+    cwd: &Path,
+    changed: &mut BTreeSet<PathBuf>,
+) {
+    if !(event.kind.is_create() || event.kind.is_modify() || event.kind.is_remove()) {
+        return;
+    }
+
+    changed.extend(
+        event
+            .paths
+            .iter()
+            .filter(|path| {
+                directories
+                    .iter()
+                    .any(|directory| scanner.is_walked(directory, path))
+            })
+            .map(|path| normalize(path, cwd)),
+    );
+}
+
+/// Make `path` relative to `cwd` where possible and drop `.` components, so that paths
+/// reported by the watcher match those of the initial scan.
+fn normalize(path: &Path, cwd: &Path) -> PathBuf {
+    path.strip_prefix(cwd)
+        .unwrap_or(path)
+        .components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}